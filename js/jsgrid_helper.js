@@ -1,6 +1,12 @@
+// requests that change data have to send the CSRF token of the page
+$.ajaxSetup({
+    headers: {"X-CSRF-Token": $('meta[name="csrf-token"]').attr("content")}
+});
+
 let table_name_to_fields;
 
-function init_table(edit_delete_enabled) {
+// capabilities tell which of insert, update and delete the user is allowed to do
+function init_table(capabilities) {
     table_name_to_fields = {
         "regions.tera": [
             {name: "region_id", title: "Region ID", type: "number", width: 50},
@@ -25,7 +31,7 @@ function init_table(edit_delete_enabled) {
                 ref_column: "city_id",
                 number: true,
             },
-            {type: "my_control", editButton: edit_delete_enabled, deleteButton: edit_delete_enabled}
+            {type: "my_control", editButton: capabilities.update, deleteButton: capabilities.delete}
         ],
         "cities.tera": [
             {name: "city_id", title: "City ID", type: "number", width: 50},
@@ -36,12 +42,12 @@ function init_table(edit_delete_enabled) {
                 ref_column: "region_id",
                 number: true,
             },
-            {type: "my_control", editButton: edit_delete_enabled, deleteButton: edit_delete_enabled}
+            {type: "my_control", editButton: capabilities.update, deleteButton: capabilities.delete}
         ],
         "continents.tera": [
             {name: "name", title: "Name", type: "text", width: 100},
             {name: "area_m2", title: "Area M2", type: "number", width: 50},
-            {type: "my_control", editButton: edit_delete_enabled, deleteButton: edit_delete_enabled}
+            {type: "my_control", editButton: capabilities.update, deleteButton: capabilities.delete}
         ],
         "countries.tera": [
             {name: "name", title: "Name", type: "text", width: 100},
@@ -56,7 +62,7 @@ function init_table(edit_delete_enabled) {
                 ref_column: "city_id",
                 number: true,
             },
-            {type: "my_control", editButton: edit_delete_enabled, deleteButton: edit_delete_enabled}
+            {type: "my_control", editButton: capabilities.update, deleteButton: capabilities.delete}
         ],
         "districts.tera": [
             {name: "district_id", title: "District ID", type: "number", width: 50},
@@ -67,7 +73,7 @@ function init_table(edit_delete_enabled) {
                 ref_column: "city_id",
                 number: true,
             },
-            {type: "my_control", editButton: edit_delete_enabled, deleteButton: edit_delete_enabled}
+            {type: "my_control", editButton: capabilities.update, deleteButton: capabilities.delete}
         ]
     };
 }
@@ -167,6 +173,14 @@ ForeignKeyField.prototype = new jsGrid.Field({
         } else {
             return parseInt(this._edit_val.val());
         }
+    },
+
+    filterTemplate: function () {
+        return this._filter_val = $("<input>");
+    },
+
+    filterValue: function () {
+        return this._filter_val.val();
     }
 });
 jsGrid.fields.foreignKeyField = ForeignKeyField;
@@ -197,14 +211,33 @@ MyControl.prototype = new jsGrid.fields.control({
 });
 jsGrid.fields.my_control = MyControl;
 
-function create_grid(gridId, page_name, edit_delete_enabled) {
-    init_table(edit_delete_enabled);
+// text columns are searched by substring, the rest by equality
+function filter_query(fields, filter) {
+    return fields
+        .filter(function (field) {
+            let value = filter[field.name];
+            return field.name !== undefined && value !== undefined && value !== "" && !Number.isNaN(value);
+        })
+        .map(function (field) {
+            let value = filter[field.name];
+            if (field.type === "text") {
+                return "&" + field.name + "~" + encodeURIComponent("%" + value + "%");
+            }
+            return "&" + field.name + "=" + encodeURIComponent(value);
+        })
+        .join("");
+}
+
+function create_grid(gridId, page_name, capabilities) {
+    init_table(capabilities);
     let previousItem;
     $(gridId).jsGrid({
         width: "100%",
 
-        inserting: true,
-        editing: edit_delete_enabled,
+        inserting: capabilities.insert,
+        filtering: true,
+        sorting: true,
+        editing: capabilities.update,
         autoload: true,
         paging: true,
         pageLoading: true,
@@ -220,7 +253,9 @@ function create_grid(gridId, page_name, edit_delete_enabled) {
             loadData: function (filter) {
                 return $.ajax({
                     type: "GET",
-                    url: "/" + page_name + "/items?page_index=" + filter.pageIndex + "&page_size=" + filter.pageSize,
+                    url: "/" + page_name + "/items?page_index=" + filter.pageIndex + "&page_size=" + filter.pageSize
+                        + filter_query(table_name_to_fields[page_name], filter)
+                        + (filter.sortField ? "&sort_field=" + filter.sortField + "&sort_order=" + filter.sortOrder : ""),
                     error: function (jqXHR, textStatus, errorThrown) {
                         console.log(textStatus)
                     }
@@ -259,7 +294,13 @@ function create_grid(gridId, page_name, edit_delete_enabled) {
                     error: function (jqXHR, textStatus, errorThrown) {
                         console.log(textStatus);
                         $(gridId).jsGrid("clearInsert");
-                        d.reject()
+                        d.reject();
+                        // someone else changed the row, show it as it is now
+                        if (jqXHR.status === 409) {
+                            show_warning("Conflict", jqXHR.responseJSON.error_msg + ": " +
+                                JSON.stringify(jqXHR.responseJSON.current));
+                            $(gridId).jsGrid("loadData");
+                        }
                     },
                     success: function (data, status, jqXHR) {
                         if (jqXHR.status === 278) {
@@ -0,0 +1,6 @@
+drop table districts cascade constraints;
+drop table cities cascade constraints;
+drop table regions cascade constraints;
+drop table countries cascade constraints;
+drop table continents cascade constraints;
+drop table users_info cascade constraints;
@@ -0,0 +1,57 @@
+create table continents (
+    name varchar2(100) primary key,
+    area_m2 number not null
+);
+
+create table countries (
+    name varchar2(100) primary key,
+    fg_continent_name varchar2(100),
+    fg_capital_city_id number(10)
+);
+
+create table regions (
+    region_id number(10) primary key,
+    region_name varchar2(100) not null,
+    fg_country_name varchar2(100),
+    population number(12) not null,
+    area_m2 number not null,
+    climate varchar2(100) not null,
+    fg_centre_city_id number(10)
+);
+
+create table cities (
+    city_id number(10) primary key,
+    city_name varchar2(100) not null,
+    fg_region_id number(10)
+);
+
+create table districts (
+    district_id number(10) primary key,
+    district_name varchar2(100) not null,
+    fg_city_id number(10)
+);
+
+create table users_info (
+    username varchar2(100) primary key,
+    password varchar2(200) not null,
+    is_admin char(1) default 'n' not null,
+    last_appearance timestamp default systimestamp not null
+);
+
+alter table countries add constraint countries_continent_fk
+    foreign key (fg_continent_name) references continents (name);
+
+alter table countries add constraint countries_capital_fk
+    foreign key (fg_capital_city_id) references cities (city_id);
+
+alter table regions add constraint regions_country_fk
+    foreign key (fg_country_name) references countries (name);
+
+alter table regions add constraint regions_centre_fk
+    foreign key (fg_centre_city_id) references cities (city_id);
+
+alter table cities add constraint cities_region_fk
+    foreign key (fg_region_id) references regions (region_id);
+
+alter table districts add constraint districts_city_fk
+    foreign key (fg_city_id) references cities (city_id);
@@ -0,0 +1 @@
+alter table users_info drop column is_disabled;
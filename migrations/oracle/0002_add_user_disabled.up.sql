@@ -0,0 +1 @@
+alter table users_info add (is_disabled char(1) default 'n' not null);
@@ -0,0 +1,33 @@
+create table roles (
+    name varchar2(100) primary key
+);
+
+create table role_grants (
+    role_name varchar2(100) not null references roles (name),
+    -- '*' grants the operation on every table
+    table_name varchar2(100) not null,
+    operation varchar2(100) not null,
+    constraint role_grants_pk primary key (role_name, table_name, operation)
+);
+
+create table user_roles (
+    username varchar2(100) not null references users_info (username),
+    role_name varchar2(100) not null references roles (name),
+    constraint user_roles_pk primary key (username, role_name)
+);
+
+insert into roles (name) values ('viewer');
+insert into roles (name) values ('editor');
+insert into roles (name) values ('admin');
+
+insert into role_grants (role_name, table_name, operation) values ('viewer', '*', 'read');
+insert into role_grants (role_name, table_name, operation) values ('editor', '*', 'read');
+insert into role_grants (role_name, table_name, operation) values ('editor', '*', 'insert');
+insert into role_grants (role_name, table_name, operation) values ('admin', '*', 'read');
+insert into role_grants (role_name, table_name, operation) values ('admin', '*', 'insert');
+insert into role_grants (role_name, table_name, operation) values ('admin', '*', 'update');
+insert into role_grants (role_name, table_name, operation) values ('admin', '*', 'delete');
+
+-- keep what users could do with the is_admin flag
+insert into user_roles (username, role_name)
+    select username, case when is_admin = 'y' then 'admin' else 'editor' end from users_info;
@@ -0,0 +1,10 @@
+create table api_tokens (
+    token_id varchar2(100) primary key,
+    username varchar2(100) not null references users_info (username),
+    -- sha-256 of the token, the token itself is only shown once
+    token_hash varchar2(64) not null unique,
+    name varchar2(100) not null,
+    scope varchar2(100) not null,
+    created_at timestamp not null,
+    expires_at timestamp
+);
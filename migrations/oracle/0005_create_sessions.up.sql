@@ -0,0 +1,9 @@
+create table sessions (
+    -- sha-256 of the id in the session cookie
+    session_id varchar2(100) primary key,
+    username varchar2(100) not null references users_info (username),
+    created_at timestamp not null,
+    last_seen timestamp not null,
+    user_agent varchar2(500),
+    ip_address varchar2(100)
+);
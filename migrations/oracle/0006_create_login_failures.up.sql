@@ -0,0 +1,7 @@
+create table login_failures (
+    -- user:<username> or ip:<address>
+    subject varchar2(200) primary key,
+    failures number(10) not null,
+    last_failure timestamp not null,
+    locked_until timestamp not null
+);
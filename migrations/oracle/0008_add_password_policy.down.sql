@@ -0,0 +1,2 @@
+drop table password_history;
+alter table users_info drop column password_changed_at;
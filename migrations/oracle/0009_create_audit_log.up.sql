@@ -0,0 +1,16 @@
+create table audit_log (
+    entry_id varchar2(100) primary key,
+    -- not a foreign key, entries outlive deleted users
+    actor varchar2(100) not null,
+    changed_at timestamp not null,
+    table_name varchar2(100) not null,
+    -- insert, update or delete
+    operation varchar2(20) not null,
+    -- key columns of the row as a JSON object
+    row_key varchar2(1000) not null,
+    -- the row as JSON before and after the change
+    old_value varchar2(4000),
+    new_value varchar2(4000)
+);
+
+create index audit_log_changed_at on audit_log (changed_at);
@@ -0,0 +1,6 @@
+drop table districts cascade;
+drop table cities cascade;
+drop table regions cascade;
+drop table countries cascade;
+drop table continents cascade;
+drop table users_info cascade;
@@ -0,0 +1 @@
+alter table users_info add column is_disabled char(1) default 'n' not null;
@@ -0,0 +1,10 @@
+create table api_tokens (
+    token_id varchar(100) primary key,
+    username varchar(100) not null references users_info (username),
+    -- sha-256 of the token, the token itself is only shown once
+    token_hash varchar(64) not null unique,
+    name varchar(100) not null,
+    scope varchar(100) not null,
+    created_at timestamptz not null,
+    expires_at timestamptz
+);
@@ -0,0 +1,9 @@
+create table sessions (
+    -- sha-256 of the id in the session cookie
+    session_id varchar(100) primary key,
+    username varchar(100) not null references users_info (username),
+    created_at timestamptz not null,
+    last_seen timestamptz not null,
+    user_agent varchar(500),
+    ip_address varchar(100)
+);
@@ -0,0 +1,7 @@
+create table login_failures (
+    -- user:<username> or ip:<address>
+    subject varchar(200) primary key,
+    failures integer not null,
+    last_failure timestamptz not null,
+    locked_until timestamptz not null
+);
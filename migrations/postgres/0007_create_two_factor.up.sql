@@ -0,0 +1,16 @@
+create table user_totp (
+    username varchar(100) primary key references users_info (username),
+    -- base32 TOTP secret, only used for logins once confirmed with a code
+    secret varchar(64) not null,
+    is_confirmed char(1) default 'n' not null,
+    -- time step of the last accepted code, so that codes can't be used twice
+    last_step bigint,
+    created_at timestamptz not null
+);
+
+create table recovery_codes (
+    username varchar(100) not null references users_info (username),
+    -- sha-256 of an unused recovery code
+    code_hash varchar(64) not null,
+    constraint recovery_codes_pk primary key (username, code_hash)
+);
@@ -0,0 +1,10 @@
+-- passwords that exist already count as changed now
+alter table users_info add column password_changed_at timestamptz default now() not null;
+
+create table password_history (
+    username varchar(100) not null references users_info (username),
+    -- argon2 hash of a previous password
+    password varchar(200) not null,
+    changed_at timestamptz not null,
+    constraint password_history_pk primary key (username, password)
+);
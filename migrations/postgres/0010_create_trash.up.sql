@@ -0,0 +1,12 @@
+create table trash (
+    trash_id varchar(100) primary key,
+    table_name varchar(100) not null,
+    -- key columns of the row as a JSON object
+    row_key varchar(1000) not null,
+    -- the deleted row as JSON, inserted again on restore
+    row_value varchar(4000) not null,
+    deleted_by varchar(100) not null,
+    deleted_at timestamptz not null
+);
+
+create index trash_deleted_at on trash (deleted_at);
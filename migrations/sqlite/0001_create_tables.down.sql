@@ -0,0 +1,10 @@
+-- sqlite checks foreign keys of the rows a drop removes, so break the cycles first
+update countries set fg_capital_city_id = null;
+update regions set fg_centre_city_id = null;
+update cities set fg_region_id = null;
+drop table districts;
+drop table cities;
+drop table regions;
+drop table countries;
+drop table continents;
+drop table users_info;
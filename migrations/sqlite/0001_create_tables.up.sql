@@ -0,0 +1,41 @@
+-- sqlite can't add constraints to existing tables, but it only checks foreign keys
+-- when rows change, so they can reference tables created later
+create table continents (
+    name text primary key,
+    area_m2 real not null
+);
+
+create table countries (
+    name text primary key,
+    fg_continent_name text references continents (name),
+    fg_capital_city_id integer references cities (city_id)
+);
+
+create table regions (
+    region_id integer primary key,
+    region_name text not null,
+    fg_country_name text references countries (name),
+    population integer not null,
+    area_m2 real not null,
+    climate text not null,
+    fg_centre_city_id integer references cities (city_id)
+);
+
+create table cities (
+    city_id integer primary key,
+    city_name text not null,
+    fg_region_id integer references regions (region_id)
+);
+
+create table districts (
+    district_id integer primary key,
+    district_name text not null,
+    fg_city_id integer references cities (city_id)
+);
+
+create table users_info (
+    username text primary key,
+    password text not null,
+    is_admin text default 'n' not null,
+    last_appearance text default (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')) not null
+);
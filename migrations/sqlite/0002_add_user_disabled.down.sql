@@ -0,0 +1,11 @@
+-- the bundled sqlite can't drop columns, so the table is copied without it
+create table users_info_old (
+    username text primary key,
+    password text not null,
+    is_admin text default 'n' not null,
+    last_appearance text default (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')) not null
+);
+insert into users_info_old (username, password, is_admin, last_appearance)
+    select username, password, is_admin, last_appearance from users_info;
+drop table users_info;
+alter table users_info_old rename to users_info;
@@ -0,0 +1 @@
+alter table users_info add column is_disabled text default 'n' not null;
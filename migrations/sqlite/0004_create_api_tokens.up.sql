@@ -0,0 +1,10 @@
+create table api_tokens (
+    token_id text primary key,
+    username text not null references users_info (username),
+    -- sha-256 of the token, the token itself is only shown once
+    token_hash text not null unique,
+    name text not null,
+    scope text not null,
+    created_at text not null,
+    expires_at text
+);
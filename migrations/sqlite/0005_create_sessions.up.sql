@@ -0,0 +1,9 @@
+create table sessions (
+    -- sha-256 of the id in the session cookie
+    session_id text primary key,
+    username text not null references users_info (username),
+    created_at text not null,
+    last_seen text not null,
+    user_agent text,
+    ip_address text
+);
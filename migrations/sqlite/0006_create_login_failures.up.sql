@@ -0,0 +1,7 @@
+create table login_failures (
+    -- user:<username> or ip:<address>
+    subject text primary key,
+    failures integer not null,
+    last_failure text not null,
+    locked_until text not null
+);
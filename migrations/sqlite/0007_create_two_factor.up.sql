@@ -0,0 +1,16 @@
+create table user_totp (
+    username text primary key references users_info (username),
+    -- base32 TOTP secret, only used for logins once confirmed with a code
+    secret text not null,
+    is_confirmed text default 'n' not null,
+    -- time step of the last accepted code, so that codes can't be used twice
+    last_step integer,
+    created_at text not null
+);
+
+create table recovery_codes (
+    username text not null references users_info (username),
+    -- sha-256 of an unused recovery code
+    code_hash text not null,
+    constraint recovery_codes_pk primary key (username, code_hash)
+);
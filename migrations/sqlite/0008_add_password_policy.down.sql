@@ -0,0 +1,16 @@
+drop table password_history;
+
+-- the bundled sqlite can't drop columns, so the table is copied without it. Other tables
+-- reference users_info by now, so their keys are only checked once the copy replaced it
+pragma defer_foreign_keys = on;
+create table users_info_old (
+    username text primary key,
+    password text not null,
+    is_admin text default 'n' not null,
+    last_appearance text default (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')) not null,
+    is_disabled text default 'n' not null
+);
+insert into users_info_old (username, password, is_admin, last_appearance, is_disabled)
+    select username, password, is_admin, last_appearance, is_disabled from users_info;
+drop table users_info;
+alter table users_info_old rename to users_info;
@@ -0,0 +1,12 @@
+-- sqlite only adds columns with constant defaults, so existing passwords
+-- are marked as changed now afterwards
+alter table users_info add column password_changed_at text default '1970-01-01T00:00:00Z' not null;
+update users_info set password_changed_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
+
+create table password_history (
+    username text not null references users_info (username),
+    -- argon2 hash of a previous password
+    password text not null,
+    changed_at text not null,
+    constraint password_history_pk primary key (username, password)
+);
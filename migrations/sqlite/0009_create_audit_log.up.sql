@@ -0,0 +1,16 @@
+create table audit_log (
+    entry_id text primary key,
+    -- not a foreign key, entries outlive deleted users
+    actor text not null,
+    changed_at text not null,
+    table_name text not null,
+    -- insert, update or delete
+    operation text not null,
+    -- key columns of the row as a JSON object
+    row_key text not null,
+    -- the row as JSON before and after the change
+    old_value text,
+    new_value text
+);
+
+create index audit_log_changed_at on audit_log (changed_at);
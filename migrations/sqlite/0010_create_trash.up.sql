@@ -0,0 +1,12 @@
+create table trash (
+    trash_id text primary key,
+    table_name text not null,
+    -- key columns of the row as a JSON object
+    row_key text not null,
+    -- the deleted row as JSON, inserted again on restore
+    row_value text not null,
+    deleted_by text not null,
+    deleted_at text not null
+);
+
+create index trash_deleted_at on trash (deleted_at);
@@ -0,0 +1,292 @@
+//! Derives for the `SchemaTable` and `RowValue` traits of the viewer.
+//!
+//! The generated code refers to the traits by their paths inside the viewer crate,
+//! so the derives can only be used there.
+//!
+//! ```ignore
+//! #[derive(SchemaTable, RowValue)]
+//! #[table("regions")]
+//! pub struct Region {
+//!     #[key]
+//!     pub region_id: usize,
+//!     #[column("region_name", len = 200)]
+//!     pub name: String,
+//!     #[column(digits = 12)]
+//!     pub population: usize,
+//!     #[column(len = 1, fixed, default = "'n'")]
+//!     pub is_capital: String,
+//!     #[references("countries.name")]
+//!     pub fg_country_name: Option<String>,
+//! }
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, Ident, Lit, LitStr, Meta, NestedMeta,
+    Token, Type,
+};
+
+struct Column {
+    field: Ident,
+    ty: Type,
+    name: String,
+    is_key: bool,
+    options: ColumnOptions,
+    /// Table and column of a `#[references("table.column")]` attribute.
+    references: Option<(String, String)>,
+}
+
+fn string_attr(attrs: &[syn::Attribute], name: &str) -> syn::Result<Option<String>> {
+    attrs
+        .iter()
+        .find(|a| a.path.is_ident(name))
+        .map(|a| a.parse_args::<LitStr>().map(|s| s.value()))
+        .transpose()
+}
+
+/// What a `#[column("name", len = 100, digits = 10, fixed, default = "'n'", default_now,
+/// sqlite_default = "'n'", unique)]` attribute gives, where every part is optional.
+#[derive(Default)]
+struct ColumnOptions {
+    name: Option<String>,
+    length: Option<u32>,
+    digits: Option<u32>,
+    fixed_length: bool,
+    /// SQL of the default value.
+    default: Option<String>,
+    default_now: bool,
+    /// SQL of the default value on SQLite, if it differs.
+    sqlite_default: Option<String>,
+    unique: bool,
+}
+
+fn column_options(attrs: &[syn::Attribute]) -> syn::Result<ColumnOptions> {
+    let mut options = ColumnOptions::default();
+    let attr = match attrs.iter().find(|a| a.path.is_ident("column")) {
+        Some(attr) => attr,
+        None => return Ok(options),
+    };
+    for item in attr.parse_args_with(Punctuated::<NestedMeta, Token![,]>::parse_terminated)? {
+        match &item {
+            NestedMeta::Lit(Lit::Str(value)) => options.name = Some(value.value()),
+            NestedMeta::Meta(Meta::NameValue(value)) => match &value.lit {
+                Lit::Int(length) if value.path.is_ident("len") => {
+                    options.length = Some(length.base10_parse()?)
+                }
+                Lit::Int(digits) if value.path.is_ident("digits") => {
+                    options.digits = Some(digits.base10_parse()?)
+                }
+                Lit::Str(sql) if value.path.is_ident("default") => {
+                    options.default = Some(sql.value())
+                }
+                Lit::Str(sql) if value.path.is_ident("sqlite_default") => {
+                    options.sqlite_default = Some(sql.value())
+                }
+                _ => {
+                    return Err(Error::new_spanned(
+                        value,
+                        "expected len = <number>, digits = <number>, default = \"<sql>\" \
+                         or sqlite_default = \"<sql>\"",
+                    ))
+                }
+            },
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("fixed") => {
+                options.fixed_length = true
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default_now") => {
+                options.default_now = true
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("unique") => options.unique = true,
+            _ => {
+                return Err(Error::new_spanned(
+                    item,
+                    "expected #[column(\"name\", len = <number>, digits = <number>, fixed, \
+                     default = \"<sql>\", default_now, sqlite_default = \"<sql>\", unique)]",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn references(attrs: &[syn::Attribute]) -> syn::Result<Option<(String, String)>> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("references")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let target = attr.parse_args::<LitStr>()?;
+    match target.value().split('.').collect::<Vec<_>>().as_slice() {
+        [table, column] => Ok(Some(((*table).to_owned(), (*column).to_owned()))),
+        _ => Err(Error::new_spanned(
+            target,
+            "expected #[references(\"table.column\")]",
+        )),
+    }
+}
+
+fn columns(input: &DeriveInput) -> syn::Result<Vec<Column>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "tables must be structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "tables must be structs with named fields",
+            ))
+        }
+    };
+    fields
+        .iter()
+        .map(|f| {
+            let field = f.ident.clone().unwrap();
+            let options = column_options(&f.attrs)?;
+            Ok(Column {
+                name: options.name.clone().unwrap_or_else(|| field.to_string()),
+                is_key: f.attrs.iter().any(|a| a.path.is_ident("key")),
+                options,
+                references: references(&f.attrs)?,
+                ty: f.ty.clone(),
+                field,
+            })
+        })
+        .collect()
+}
+
+fn schema_table(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let table = string_attr(&input.attrs, "table")?.ok_or_else(|| {
+        Error::new_spanned(
+            ident,
+            "missing #[table(\"...\")] attribute with the table name",
+        )
+    })?;
+    let columns = columns(input)?;
+    if !columns.iter().any(|c| c.is_key) {
+        return Err(Error::new_spanned(
+            ident,
+            "at least one field has to be marked with #[key]",
+        ));
+    }
+
+    let names = columns.iter().map(|c| &c.name);
+    let fields = columns.iter().map(|c| &c.field);
+    let key_names = columns.iter().filter(|c| c.is_key).map(|c| &c.name);
+    let key_fields = columns.iter().filter(|c| c.is_key).map(|c| &c.field);
+    let definitions = columns.iter().map(|c| {
+        let name = &c.name;
+        let ty = &c.ty;
+        let references = match &c.references {
+            Some((table, column)) => quote! {
+                Some(crate::ddl::ForeignKey { table: #table, column: #column })
+            },
+            None => quote! { None },
+        };
+        let length = match c.options.length {
+            Some(length) => quote! { Some(#length) },
+            None => quote! { None },
+        };
+        let digits = match c.options.digits {
+            Some(digits) => quote! { Some(#digits) },
+            None => quote! { None },
+        };
+        let fixed_length = c.options.fixed_length;
+        let default = match (&c.options.default, c.options.default_now) {
+            (Some(sql), _) => quote! { Some(crate::ddl::ColumnDefault::Value(#sql)) },
+            (None, true) => quote! { Some(crate::ddl::ColumnDefault::Now) },
+            (None, false) => quote! { None },
+        };
+        let sqlite_default = match &c.options.sqlite_default {
+            Some(sql) => quote! { Some(#sql) },
+            None => quote! { None },
+        };
+        let unique = c.options.unique;
+        quote! {
+            crate::ddl::Column {
+                name: #name,
+                column_type: <#ty as crate::ddl::SqlType>::COLUMN_TYPE,
+                nullable: <#ty as crate::ddl::SqlType>::NULLABLE,
+                length: #length,
+                digits: #digits,
+                fixed_length: #fixed_length,
+                default: #default,
+                sqlite_default: #sqlite_default,
+                unique: #unique,
+                references: #references,
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl crate::database_operations::SchemaTable for #ident {
+            fn column_names() -> Vec<&'static str> {
+                vec![#(#names),*]
+            }
+
+            fn table_name() -> &'static str {
+                #table
+            }
+
+            fn values(&self) -> Vec<crate::backend::SqlValue> {
+                vec![#(self.#fields.clone().into()),*]
+            }
+
+            fn key_attrs() -> Vec<&'static str> {
+                vec![#(#key_names),*]
+            }
+
+            fn key_attr_values(&self) -> Vec<crate::backend::SqlValue> {
+                vec![#(self.#key_fields.clone().into()),*]
+            }
+
+            fn columns() -> Vec<crate::ddl::Column> {
+                vec![#(#definitions),*]
+            }
+        }
+    })
+}
+
+fn row_value(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let columns = columns(input)?;
+
+    let names = columns.iter().map(|c| &c.name);
+    let fields = columns.iter().map(|c| &c.field);
+
+    Ok(quote! {
+        impl crate::backend::RowValue for #ident {
+            fn get(row: &crate::backend::Row) -> Result<Self, crate::error::Error> {
+                Ok(#ident {
+                    #(#fields: row.get(#names)?),*
+                })
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(SchemaTable, attributes(table, key, column, references))]
+pub fn derive_schema_table(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    schema_table(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(RowValue, attributes(column, references))]
+pub fn derive_row_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    row_value(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
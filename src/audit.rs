@@ -0,0 +1,54 @@
+//! Browsing of `audit_log`, where `insert_data`, `update_data` and `delete_data` record
+//! who changed which row, along with the row before and after the change.
+//!
+//! Entries are filtered like table rows, e.g. `actor=bob`, `table_name=regions` or
+//! `changed_at>=2020-05-01T00:00:00Z`, and come newest first unless sorted otherwise.
+
+use crate::auth::Admin;
+use crate::database::OracleConnection;
+use crate::database_operations::{count_rows, load_data, page_records};
+use crate::error::Error;
+use crate::filter::{conditions, sort_columns, FilterQuery, SortQuery};
+use crate::schema::AuditEntry;
+use log::info;
+use rocket::Route;
+use rocket_contrib::json::JsonValue;
+
+lazy_static! {
+    pub static ref AUDIT_ROUTES: Vec<Route> = routes![list_audit_log];
+}
+
+#[get("/admin/audit_log?<page_index>&<page_size>&<sort_field>&<sort_order>&<filters..>")]
+fn list_audit_log(
+    conn: OracleConnection,
+    page_index: usize,
+    page_size: usize,
+    sort_field: Option<String>,
+    sort_order: Option<String>,
+    filters: FilterQuery,
+    admin: Admin,
+) -> Result<JsonValue, Error> {
+    let (record_start, record_end) = page_records(page_index, page_size)?;
+    info!(
+        "{}",
+        format!(
+            "Admin {:?} read the audit log with filters {:?}",
+            admin, filters
+        )
+    );
+    let conditions = conditions::<AuditEntry>(&filters)?;
+    let sort = match sort_field {
+        Some(_) => SortQuery {
+            fields: sort_field,
+            orders: sort_order,
+        },
+        None => SortQuery {
+            fields: Some("changed_at".to_owned()),
+            orders: Some("desc".to_owned()),
+        },
+    };
+    let sort = sort_columns::<AuditEntry>(&sort)?;
+    let rows = count_rows::<AuditEntry>(&*conn, &conditions)?;
+    let entries = load_data::<AuditEntry>(&*conn, record_start, record_end, &conditions, &sort)?;
+    Ok(json!({ "itemsCount": rows, "data": entries }))
+}
@@ -1,17 +1,32 @@
-use crate::database_operations::{get_user, update_data};
-use crate::database_oracle::{DbConnection, OracleConnection};
+use crate::database::{DbConnection, OracleConnection};
+use crate::database_operations::{
+    delete_data, get_token_by_hash, get_user, has_grant, is_admin, update_data,
+};
 use crate::error::Error;
-use crate::schema::UserInfo;
-use chrono::Utc;
+use crate::password::PasswordPolicy;
+use crate::schema::{Session, UserInfo};
+use crate::sessions::{self, SessionConfig, SESSION_COOKIE};
+use crate::table_registry::table_name;
+use crate::tokens::{self, TokenScope};
+use chrono::{Duration, Utc};
 use rocket::http::{Cookie, Status};
 use rocket::request::Form;
 use rocket::request::{self, FromRequest};
-use rocket::{Outcome, Request};
+use rocket::{Outcome, Request, State};
+use std::marker::PhantomData;
 
 #[derive(FromForm, Debug, Default)]
 pub struct UserFullData {
     pub username: String,
     pub password: String,
+    pub csrf_token: String,
+}
+
+/// Second login step, with a code from the authenticator app or a recovery code.
+#[derive(FromForm, Debug, Default)]
+pub struct TwoFactorForm {
+    pub code: String,
+    pub csrf_token: String,
 }
 
 #[derive(Debug, Default)]
@@ -23,13 +38,71 @@ pub struct UserData {
 pub struct User {
     pub is_admin: bool,
     pub data: UserData,
+    /// Scope of the API token the request was authenticated with, if it wasn't the cookie.
+    pub token: Option<TokenScope>,
+    /// Key of the session the request was authenticated with.
+    pub session: Option<String>,
+    /// Whether the password is older than the policy allows.
+    pub password_expired: bool,
 }
 impl User {
-    fn new(username: String, password: String, is_admin: bool) -> Self {
-        User {
+    /// Whether the request may change anything, read-only tokens can't.
+    pub fn can_write(&self) -> bool {
+        self.token != Some(TokenScope::Read)
+    }
+}
+
+/// Paths that users with an expired password can still use, to change it or leave.
+const PASSWORD_CHANGE_PATHS: [&str; 3] = ["/password.tera", "/password", "/signout.tera"];
+
+/// Set in the request cache when the `User` guard failed because the password
+/// has expired, so that the 401 catcher sends the user to the password change page.
+#[derive(Debug)]
+pub struct PasswordExpired(pub bool);
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub fn bearer_token<'a>(request: &'a Request) -> Option<&'a str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn user_from_token(request: &Request, token: &str) -> request::Outcome<User, ()> {
+    let connection = request.guard::<OracleConnection>()?;
+    let api_token = match get_token_by_hash(&*connection, &tokens::hash(token)) {
+        Ok(Some(api_token)) => api_token,
+        Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+    };
+    let scope = match TokenScope::from_name(&api_token.scope) {
+        Some(scope) if api_token.expires_at.iter().all(|e| *e > Utc::now()) => scope,
+        _ => return Outcome::Failure((Status::Unauthorized, ())),
+    };
+    let user = get_user(
+        &*connection,
+        &UserInfo {
+            username: api_token.username,
+            ..UserInfo::default()
+        },
+    )
+    .and_then(|user| match user {
+        Some(u) if u.is_disabled != "y" => Ok(Some((is_admin(&*connection, &u.username)?, u))),
+        _ => Ok(None),
+    });
+    match user {
+        Ok(Some((is_admin, u))) => Outcome::Success(User {
             is_admin,
-            data: UserData { username },
-        }
+            data: UserData {
+                username: u.username,
+            },
+            token: Some(scope),
+            session: None,
+            password_expired: false,
+        }),
+        Ok(None) => Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => Outcome::Failure((Status::InternalServerError, ())),
     }
 }
 
@@ -37,68 +110,101 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        let cook = request.cookies().get_private("user_name");
-        let cook = match cook {
-            Some(c) => c,
+        // scripts send a token instead of keeping a session
+        if let Some(token) = bearer_token(request) {
+            return user_from_token(request, token);
+        }
+
+        let cookie = match request.cookies().get_private(SESSION_COOKIE) {
+            Some(cookie) => cookie,
             None => return Outcome::Failure((Status::Unauthorized, ())),
         };
-
-        let connection = request.guard::<OracleConnection>();
-        let connection = match connection {
-            Outcome::Success(s) => s,
-            Outcome::Failure(f) => return Outcome::Failure(f),
-            Outcome::Forward(f) => return Outcome::Forward(f),
+        let connection = request.guard::<OracleConnection>()?;
+        let config = request.guard::<State<SessionConfig>>()?;
+        let policy = request.guard::<State<PasswordPolicy>>()?;
+        let session = match sessions::find(&*connection, cookie.value()) {
+            Ok(Some(session)) => session,
+            Ok(None) => return signed_out(request),
+            Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let user = match get_user(
+            &*connection,
+            &UserInfo {
+                username: session.username.clone(),
+                ..UserInfo::default()
+            },
+        ) {
+            Ok(user) => user,
+            Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let now = Utc::now();
+        let user = match user {
+            Some(user) if user.is_disabled != "y" && !config.has_expired(&session, now) => user,
+            // disabled users are signed out right away
+            _ => {
+                if delete_data(&*connection, None, &session).is_err() {
+                    return Outcome::Failure((Status::InternalServerError, ()));
+                }
+                return signed_out(request);
+            }
         };
-        Outcome::Success(
-            match get_user(
+        let password_expired = policy.has_expired(&user);
+        if password_expired && !PASSWORD_CHANGE_PATHS.contains(&request.uri().path()) {
+            request.local_cache(|| PasswordExpired(true));
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+        // last seen is written at most once a minute rather than on every request
+        if session.last_seen + Duration::minutes(1) < now {
+            let touched = update_data(
                 &*connection,
-                &UserInfo {
-                    username: cook.value().to_owned(),
-                    ..UserInfo::default()
+                None,
+                &session,
+                &Session {
+                    last_seen: now,
+                    ..session.clone()
                 },
-            ) {
-                Ok(Some(UserInfo {
-                    username,
-                    password,
-                    is_admin,
-                    last_appearance,
-                })) => {
-                    let u = UserInfo {
-                        username: username.clone(),
-                        password: password.clone(),
-                        is_admin: is_admin.clone(),
-                        last_appearance,
-                    };
-                    // check if the user needs to relogin
-                    if last_appearance < Utc::now() - chrono::Duration::minutes(5) {
-                        println!("relogin! {}", username);
-                        request.cookies().remove_private(Cookie::named("user_name"));
-                        return Outcome::Failure((Status::Unauthorized, ()));
-                    }
-                    // update last seen timer
-                    request
-                        .cookies()
-                        .add_private(Cookie::new("user_name", username.clone()));
-                    match update_data(
-                        &*connection,
-                        &u,
-                        &UserInfo {
-                            last_appearance: Utc::now(),
-                            ..u.clone()
-                        },
-                    ) {
-                        Ok(_) => (),
-                        Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
-                    };
-                    User::new(username, password, is_admin == "y")
-                }
-                Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+            )
+            .and_then(|_| {
+                update_data(
+                    &*connection,
+                    None,
+                    &user,
+                    &UserInfo {
+                        last_appearance: now,
+                        ..user.clone()
+                    },
+                )
+            });
+            match touched {
+                // another request changed the user at the same time, which is just as recent
+                Ok(_) | Err(Error::ConflictError { .. }) => {}
                 Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+            }
+        }
+        let is_admin = match is_admin(&*connection, &user.username) {
+            Ok(is_admin) => is_admin,
+            Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        Outcome::Success(User {
+            is_admin,
+            data: UserData {
+                username: user.username,
             },
-        )
+            token: None,
+            session: Some(session.session_id),
+            password_expired,
+        })
     }
 }
 
+fn signed_out(request: &Request) -> request::Outcome<User, ()> {
+    request
+        .cookies()
+        .remove_private(Cookie::named(SESSION_COOKIE));
+    Outcome::Failure((Status::Unauthorized, ()))
+}
+
+/// A user with the admin role.
 #[derive(Debug)]
 pub struct Admin {
     pub data: UserData,
@@ -110,7 +216,8 @@ impl<'a, 'r> FromRequest<'a, 'r> for Admin {
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
         let user = request.guard::<User>()?;
 
-        if user.is_admin {
+        // user management changes things, so read-only tokens can't be used for it
+        if user.is_admin && user.can_write() {
             Outcome::Success(Admin {
                 data: UserData {
                     username: user.data.username,
@@ -121,3 +228,77 @@ impl<'a, 'r> FromRequest<'a, 'r> for Admin {
         }
     }
 }
+
+/// An operation on table rows that roles can be granted.
+pub trait Operation {
+    /// Name stored in `role_grants.operation`.
+    const NAME: &'static str;
+}
+
+#[derive(Debug)]
+pub struct Read;
+
+#[derive(Debug)]
+pub struct Insert;
+
+#[derive(Debug)]
+pub struct Update;
+
+#[derive(Debug)]
+pub struct Delete;
+
+impl Operation for Read {
+    const NAME: &'static str = "read";
+}
+
+impl Operation for Insert {
+    const NAME: &'static str = "insert";
+}
+
+impl Operation for Update {
+    const NAME: &'static str = "update";
+}
+
+impl Operation for Delete {
+    const NAME: &'static str = "delete";
+}
+
+pub const OPERATIONS: [&str; 4] = [Read::NAME, Insert::NAME, Update::NAME, Delete::NAME];
+
+/// A user whose roles allow the operation `O` on the table named by the first segment
+/// of the request path, as in `/<table_name>/items`.
+#[derive(Debug)]
+pub struct Grant<O: Operation> {
+    pub user: User,
+    operation: PhantomData<O>,
+}
+
+impl<'a, 'r, O: Operation> FromRequest<'a, 'r> for Grant<O> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let user = request.guard::<User>()?;
+        if O::NAME != Read::NAME && !user.can_write() {
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+        let table = match request.get_param::<String>(0) {
+            Some(Ok(table)) => table,
+            _ => return Outcome::Failure((Status::NotFound, ())),
+        };
+        let connection = request.guard::<OracleConnection>()?;
+
+        match has_grant(
+            &*connection,
+            &user.data.username,
+            table_name(&table),
+            O::NAME,
+        ) {
+            Ok(true) => Outcome::Success(Grant {
+                user,
+                operation: PhantomData,
+            }),
+            Ok(false) => Outcome::Failure((Status::Forbidden, ())),
+            Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::Error;
+use chrono::{DateTime, Utc};
+use std::convert::TryFrom;
+
+/// A single value bound to or read from a query, independent of the database in use.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    DateTime(DateTime<Utc>),
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_owned())
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(value: i32) -> Self {
+        SqlValue::Int(value.into())
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::Int(value)
+    }
+}
+
+impl From<usize> for SqlValue {
+    fn from(value: usize) -> Self {
+        SqlValue::Int(value as i64)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::Float(value)
+    }
+}
+
+impl From<DateTime<Utc>> for SqlValue {
+    fn from(value: DateTime<Utc>) -> Self {
+        SqlValue::DateTime(value)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// Conversion from a `SqlValue` read from the database into a Rust type.
+///
+/// Backends report values in the widest type they have, so numbers may come back
+/// as either `Int` or `Float` and timestamps may come back as text.
+pub trait FromSqlValue: Sized {
+    fn from_sql_value(value: &SqlValue) -> Option<Self>;
+}
+
+impl FromSqlValue for String {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        match value {
+            SqlValue::Text(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromSqlValue for i64 {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        match value {
+            SqlValue::Int(v) => Some(*v),
+            SqlValue::Float(v) if v.fract() == 0.0 => Some(*v as i64),
+            _ => None,
+        }
+    }
+}
+
+impl FromSqlValue for i32 {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        i64::from_sql_value(value).and_then(|v| i32::try_from(v).ok())
+    }
+}
+
+impl FromSqlValue for usize {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        i64::from_sql_value(value).and_then(|v| usize::try_from(v).ok())
+    }
+}
+
+impl FromSqlValue for f64 {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        match value {
+            SqlValue::Float(v) => Some(*v),
+            SqlValue::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromSqlValue for DateTime<Utc> {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        match value {
+            SqlValue::DateTime(v) => Some(*v),
+            SqlValue::Text(v) => DateTime::parse_from_rfc3339(v)
+                .ok()
+                .map(|v| v.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromSqlValue> FromSqlValue for Option<T> {
+    fn from_sql_value(value: &SqlValue) -> Option<Self> {
+        match value {
+            SqlValue::Null => Some(None),
+            v => T::from_sql_value(v).map(Some),
+        }
+    }
+}
+
+/// A row returned by `Backend::query`. Columns are looked up case-insensitively,
+/// since Oracle reports them in upper case.
+#[derive(Debug, Clone)]
+pub struct Row {
+    columns: Vec<String>,
+    values: Vec<SqlValue>,
+}
+
+impl Row {
+    pub fn new(columns: Vec<String>, values: Vec<SqlValue>) -> Self {
+        Row { columns, values }
+    }
+
+    pub fn get<T: FromSqlValue>(&self, column: &str) -> Result<T, Error> {
+        let index = self
+            .columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(column))
+            .ok_or_else(|| Error::ColumnDoesntExistError {
+                column: column.to_owned(),
+            })?;
+        self.get_at(index)
+    }
+
+    pub fn get_at<T: FromSqlValue>(&self, index: usize) -> Result<T, Error> {
+        let value = self
+            .values
+            .get(index)
+            .ok_or_else(|| Error::ColumnDoesntExistError {
+                column: index.to_string(),
+            })?;
+        T::from_sql_value(value).ok_or_else(|| Error::ColumnTypeError {
+            column: self.columns[index].clone(),
+            value: value.clone(),
+        })
+    }
+}
+
+pub trait RowValue: Sized {
+    fn get(row: &Row) -> Result<Self, Error>;
+}
+
+/// SQL flavour of a backend, for the statements that can't be written portably,
+/// like DDL and catalog queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Oracle,
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Oracle => "oracle",
+            Dialect::Sqlite => "sqlite",
+            Dialect::Postgres => "postgres",
+        }
+    }
+}
+
+/// A database the viewer can store its tables in.
+///
+/// Statements are built by `database_operations` and only differ between backends
+/// in placeholder syntax, pagination, `returning` support and timestamp comparison, which
+/// the backend provides.
+/// Changes are not visible to other connections until `commit` is called.
+pub trait Backend {
+    fn dialect(&self) -> Dialect;
+
+    /// Placeholder for the bind parameter at `index`, starting from 1.
+    fn placeholder(&self, index: usize) -> String;
+
+    /// Wraps an ordered `select` so that only rows `record_start..=record_end` (starting from 1)
+    /// are returned. The added bind parameters are numbered from `first_param`.
+    fn paginate(
+        &self,
+        sql: &str,
+        first_param: usize,
+        record_start: usize,
+        record_end: usize,
+    ) -> (String, Vec<SqlValue>);
+
+    fn query(&self, sql: &str, params: &[SqlValue]) -> Result<Vec<Row>, Error>;
+
+    /// Runs a statement and returns the number of affected rows.
+    fn execute(&self, sql: &str, params: &[SqlValue]) -> Result<u64, Error>;
+
+    /// Runs an insert or update and returns `columns` of the rows as they were stored,
+    /// or `None` if rows were changed but the database can't report them.
+    fn execute_returning(
+        &self,
+        sql: &str,
+        params: &[SqlValue],
+        _columns: &[&str],
+    ) -> Result<Option<Vec<Row>>, Error> {
+        match self.execute(sql, params)? {
+            0 => Ok(Some(Vec::new())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Condition that the timestamp column holds the time bound at `placeholder`,
+    /// compared at the precision the database stores timestamps with.
+    fn datetime_equals(&self, column: &str, placeholder: &str) -> String {
+        format!("{}={}", column, placeholder)
+    }
+
+    fn commit(&self) -> Result<(), Error>;
+
+    fn rollback(&self) -> Result<(), Error>;
+
+    fn ping(&self) -> Result<(), Error>;
+
+    fn has_broken(&self) -> bool;
+}
@@ -0,0 +1,115 @@
+//! Changes of rows across tables that are committed together.
+//!
+//! A batch is a list of inserts, updates and deletes with items shaped like for the
+//! `/<table_name>/items` routes. They run in order in one transaction, and if one of them
+//! fails the changes made before it are rolled back and the ones after it aren't run.
+
+use crate::auth::User;
+use crate::csrf::Csrf;
+use crate::database::{DbConnection, OracleConnection};
+use crate::database_operations::{has_grant, transaction};
+use crate::error::Error;
+use crate::read_insert_update_delete::TABLES;
+use crate::table_registry::table_name;
+use log::info;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use rocket_contrib::json::{Json, JsonValue};
+use serde::Deserialize;
+use serde_json::Value;
+
+lazy_static! {
+    pub static ref BATCH_ROUTES: Vec<Route> = routes![apply_batch];
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchOperation {
+    pub table: String,
+    /// `insert`, `update` or `delete`.
+    pub operation: String,
+    pub item: Value,
+}
+
+fn apply(
+    connection: &DbConnection,
+    user: &User,
+    operation: BatchOperation,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&operation.table)?;
+    if !user.can_write()
+        || !has_grant(
+            connection,
+            &user.data.username,
+            table_name(&operation.table),
+            &operation.operation,
+        )?
+    {
+        return Err(Error::ForbiddenError {
+            table: operation.table,
+            operation: operation.operation,
+        });
+    }
+    table.apply(
+        connection,
+        &user.data.username,
+        &operation.operation,
+        operation.item,
+    )
+}
+
+/// Responds with the result of every operation: `ok` with the row if the batch was
+/// committed, otherwise `rolled back` for the operations before the failed one and
+/// `skipped` for the ones after it.
+#[post("/batch", format = "json", data = "<operations>")]
+fn apply_batch(
+    conn: OracleConnection,
+    operations: Json<Vec<BatchOperation>>,
+    user: User,
+    _csrf: Csrf,
+) -> Result<status::Custom<JsonValue>, Error> {
+    let operations = operations.into_inner();
+    let count = operations.len();
+    info!(
+        "{}",
+        format!("User {:?} applied a batch of {:?}", user, operations)
+    );
+    let mut rows = Vec::new();
+    let outcome = transaction(&*conn, || {
+        for operation in operations {
+            rows.push(apply(&*conn, &user, operation)?);
+        }
+        Ok(())
+    });
+    match outcome {
+        Ok(()) => {
+            let results = rows
+                .into_iter()
+                .map(|row| json!({ "status": "ok", "item": row }).0)
+                .collect::<Vec<_>>();
+            Ok(status::Custom(
+                Status::Ok,
+                json!({ "committed": true, "results": results }),
+            ))
+        }
+        // every operation went through, so it's the commit that failed
+        Err(error) if rows.len() == count => Err(error),
+        Err(error) => {
+            let failed = rows.len();
+            info!(
+                "{}",
+                format!("Operation {} of the batch failed: {}", failed, error)
+            );
+            let mut results = rows
+                .into_iter()
+                .map(|row| json!({ "status": "rolled back", "item": row }).0)
+                .collect::<Vec<_>>();
+            results.push(json!({ "status": "failed", "error_msg": error.to_string() }).0);
+            results.extend((failed + 1..count).map(|_| json!({ "status": "skipped" }).0));
+            Ok(status::Custom(
+                Status::BadRequest,
+                json!({ "committed": false, "results": results }),
+            ))
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Protection against forged requests from other sites.
+//!
+//! Pages get a random token that is also kept in a private cookie, and requests that change
+//! data have to send it back in the `X-CSRF-Token` header (the login form sends it as a
+//! field). Other sites can't read the token, so they can't make the browser send it.
+//! Requests authenticated with an API token don't carry the session cookie and need none.
+
+use crate::auth::bearer_token;
+use crate::tokens::random_string;
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request};
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The token for the page, created along with its cookie on the first page view.
+#[derive(Debug)]
+pub struct CsrfToken(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let mut cookies = request.cookies();
+        let token = match cookies.get_private(CSRF_COOKIE) {
+            Some(cookie) => cookie.value().to_owned(),
+            None => {
+                let token = random_string(32);
+                cookies.add_private(Cookie::new(CSRF_COOKIE, token.clone()));
+                token
+            }
+        };
+        Outcome::Success(CsrfToken(token))
+    }
+}
+
+/// Whether the token sent with the request is the one of the cookie.
+pub fn is_valid(cookies: &mut Cookies, token: &str) -> bool {
+    !token.is_empty() && cookies.get_private(CSRF_COOKIE).as_ref().map(Cookie::value) == Some(token)
+}
+
+/// A request that may change data, it has a valid `X-CSRF-Token` header
+/// or is authenticated with an API token.
+#[derive(Debug)]
+pub struct Csrf;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Csrf {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let token = request.headers().get_one(CSRF_HEADER).unwrap_or("");
+        if bearer_token(request).is_some() || is_valid(&mut request.cookies(), token) {
+            Outcome::Success(Csrf)
+        } else {
+            Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
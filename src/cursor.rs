@@ -0,0 +1,236 @@
+//! Keyset pagination.
+//!
+//! A cursor holds the values of the order columns (the sort columns followed by the key)
+//! of the row a page starts after, so the database can seek to it with an index instead of
+//! sorting and skipping all the rows before the page. Cursors are base64 encoded JSON and
+//! only valid for the sorting they were created with.
+
+use crate::backend::{RowValue, SqlValue};
+use crate::database::DbConnection;
+use crate::database_operations::{load_data_after, SchemaTable};
+use crate::ddl::ColumnType;
+use crate::error::Error;
+use crate::filter::{order_columns, Condition, SortColumn};
+use chrono::{DateTime, Utc};
+use log::error;
+use rocket::config::ConfigError;
+use rocket::fairing::AdHoc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// Largest page of the item routes from `Rocket.toml`, managed by `config_fairing`.
+#[derive(Debug, Clone, Copy)]
+pub struct PageConfig {
+    pub max_page_size: usize,
+}
+
+impl PageConfig {
+    /// Rejects pages larger than `max_page_size`, which would read most of a big table
+    /// in one request however it's paged.
+    pub fn check(&self, page_size: usize) -> Result<(), Error> {
+        if page_size > self.max_page_size {
+            Err(Error::InvalidPageSizeError {
+                page_size,
+                max_page_size: self.max_page_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Reads the largest page size, by default 1000 rows.
+pub fn config_fairing() -> AdHoc {
+    AdHoc::on_attach("Page size", |rocket| {
+        let config = match rocket.config().get_int("max_page_size") {
+            Ok(value) if value > 0 => Ok(PageConfig {
+                max_page_size: value as usize,
+            }),
+            Ok(_) => Err("max_page_size has to be positive".to_owned()),
+            Err(ConfigError::Missing(_)) => Ok(PageConfig {
+                max_page_size: 1000,
+            }),
+            Err(e) => Err(e.to_string()),
+        };
+        match config {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                error!("Invalid page size configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    columns: Vec<String>,
+    values: Vec<Value>,
+    /// Whether the page is the one before the row rather than after it.
+    backward: bool,
+}
+
+/// A page of rows with the cursors of the pages around it, if there are any.
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+fn to_json(value: &SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Int(v) => Value::from(*v),
+        SqlValue::Float(v) => Value::from(*v),
+        SqlValue::Text(v) => Value::from(v.as_str()),
+        SqlValue::DateTime(v) => Value::from(v.to_rfc3339()),
+    }
+}
+
+fn from_json(column_type: ColumnType, value: &Value) -> Option<SqlValue> {
+    match column_type {
+        ColumnType::Integer => value.as_i64().map(SqlValue::Int),
+        ColumnType::Integer32 => value
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .map(SqlValue::from),
+        ColumnType::Float => value.as_f64().map(SqlValue::Float),
+        ColumnType::Text => value.as_str().map(SqlValue::from),
+        ColumnType::DateTime => value
+            .as_str()
+            .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+            .map(|v| SqlValue::DateTime(v.with_timezone(&Utc))),
+    }
+}
+
+fn encode<T: SchemaTable>(item: &T, order: &[SortColumn], backward: bool) -> String {
+    let names = T::column_names();
+    let values = item.values();
+    let cursor = Cursor {
+        columns: order.iter().map(|s| s.column.to_owned()).collect(),
+        values: order
+            .iter()
+            .map(|s| {
+                names
+                    .iter()
+                    .position(|name| *name == s.column)
+                    .map_or(Value::Null, |i| to_json(&values[i]))
+            })
+            .collect(),
+        backward,
+    };
+    base64::encode_config(
+        serde_json::to_vec(&cursor).expect("cursor is always serializable"),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Order column values and direction of the cursor, which has to match `order`.
+fn decode<T: SchemaTable>(
+    cursor: &str,
+    order: &[SortColumn],
+) -> Result<(Vec<SqlValue>, bool), Error> {
+    let invalid = |reason: &str| Error::InvalidCursorError {
+        reason: reason.to_owned(),
+    };
+    let cursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+        .ok_or_else(|| invalid("malformed cursor"))?;
+    if cursor.columns.len() != order.len()
+        || cursor.values.len() != order.len()
+        || cursor.columns.iter().zip(order).any(|(c, s)| c != s.column)
+    {
+        return Err(invalid("cursor was created with a different sorting"));
+    }
+    let columns = T::columns();
+    let values = order
+        .iter()
+        .zip(&cursor.values)
+        .map(|(s, value)| {
+            columns
+                .iter()
+                .find(|c| c.name == s.column)
+                .and_then(|c| from_json(c.column_type, value))
+                .ok_or_else(|| invalid("cursor value doesn't match the column type"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((values, cursor.backward))
+}
+
+/// Loads `page_size` rows matching `conditions` that come after (or before) the `cursor` row,
+/// or the first page if there's no cursor.
+///
+/// Rows are compared by their order columns, so sorting by nullable columns isn't supported.
+pub fn load_page<T>(
+    connection: &DbConnection,
+    conditions: &[Condition],
+    sort: &[SortColumn],
+    cursor: Option<&str>,
+    page_size: usize,
+) -> Result<Page<T>, Error>
+where
+    T: SchemaTable + RowValue,
+{
+    let order = order_columns::<T>(sort);
+    let columns = T::columns();
+    if let Some(nullable) = order
+        .iter()
+        .find(|s| columns.iter().any(|c| c.name == s.column && c.nullable))
+    {
+        return Err(Error::InvalidSortError {
+            sort: nullable.column.to_owned(),
+            reason: "cursor pagination can't sort by a nullable column".to_owned(),
+        });
+    }
+    let cursor = match cursor {
+        Some(cursor) if !cursor.is_empty() => Some(decode::<T>(cursor, &order)?),
+        _ => None,
+    };
+    let backward = matches!(cursor, Some((_, true)));
+    // the page before the cursor row is the page after it in the reversed order
+    let query_order = if backward {
+        order
+            .iter()
+            .map(|s| SortColumn {
+                descending: !s.descending,
+                ..*s
+            })
+            .collect()
+    } else {
+        order.clone()
+    };
+
+    // one more row tells if there is another page
+    let mut data = load_data_after::<T>(
+        connection,
+        conditions,
+        &query_order,
+        cursor.as_ref().map(|(values, _)| values.as_slice()),
+        page_size + 1,
+    )?;
+    let has_more = data.len() > page_size;
+    data.truncate(page_size);
+    if backward {
+        data.reverse();
+    }
+    // going forward from the cursor row, there's at least that row before the page
+    let (has_prev, has_next) = if backward {
+        (has_more, true)
+    } else {
+        (cursor.is_some(), has_more)
+    };
+
+    Ok(Page {
+        next: data
+            .last()
+            .filter(|_| has_next)
+            .map(|item| encode(item, &order, false)),
+        prev: data
+            .first()
+            .filter(|_| has_prev)
+            .map(|item| encode(item, &order, true)),
+        data,
+    })
+}
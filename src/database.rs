@@ -0,0 +1,128 @@
+use crate::backend::Backend;
+use crate::database_oracle::OracleBackend;
+use crate::database_postgres::PostgresBackend;
+use crate::database_sqlite::SqliteBackend;
+use crate::error::Error;
+use r2d2_oracle::{r2d2, OracleConnectionManager};
+use rocket::config::Value;
+use rocket_contrib::databases::{DatabaseConfig, Poolable};
+use std::cell::Cell;
+
+pub enum DbConnectionManager {
+    Oracle(OracleConnectionManager),
+    /// Path to the database file, or a SQLite URI for a shared in-memory database.
+    Sqlite(String),
+    /// Connection string in either the key-value or the URL format.
+    Postgres(String),
+}
+
+impl r2d2::ManageConnection for DbConnectionManager {
+    type Connection = DbConnection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(match self {
+            DbConnectionManager::Oracle(manager) => {
+                DbConnection::new(Box::new(OracleBackend::new(manager.connect()?)))
+            }
+            DbConnectionManager::Sqlite(path) => {
+                DbConnection::new(Box::new(SqliteBackend::open(path)?))
+            }
+            DbConnectionManager::Postgres(params) => {
+                DbConnection::new(Box::new(PostgresBackend::connect(params)?))
+            }
+        })
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        conn.backend.ping()
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.backend.has_broken()
+    }
+}
+
+pub struct DbConnection {
+    backend: Box<dyn Backend + Send>,
+    /// Transactions running on the connection, see `enter_transaction`.
+    transactions: Cell<usize>,
+}
+
+/// A running transaction, which ends when this is dropped.
+pub struct TransactionDepth<'a>(&'a Cell<usize>);
+
+impl TransactionDepth<'_> {
+    /// Whether the transaction isn't part of another one, so it has to commit its changes.
+    pub fn is_outermost(&self) -> bool {
+        self.0.get() == 1
+    }
+}
+
+impl Drop for TransactionDepth<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+impl DbConnection {
+    fn new(backend: Box<dyn Backend + Send>) -> Self {
+        DbConnection {
+            backend,
+            transactions: Cell::new(0),
+        }
+    }
+
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
+    }
+
+    /// Marks a transaction as running until the returned value is dropped.
+    pub fn enter_transaction(&self) -> TransactionDepth<'_> {
+        self.transactions.set(self.transactions.get() + 1);
+        TransactionDepth(&self.transactions)
+    }
+}
+
+impl Poolable for DbConnection {
+    type Manager = DbConnectionManager;
+    type Error = Error;
+
+    fn pool(config: DatabaseConfig) -> Result<r2d2::Pool<Self::Manager>, Self::Error> {
+        fn extract_string<'a>(
+            config: &'a DatabaseConfig,
+            extra_name: &'static str,
+        ) -> Option<&'a String> {
+            config.extras.get(extra_name).and_then(|v| {
+                if let Value::String(sv) = v {
+                    Some(sv)
+                } else {
+                    None
+                }
+            })
+        }
+        let manager = match extract_string(&config, "backend").map(String::as_str) {
+            None | Some("oracle") => {
+                let uname = extract_string(&config, "username").unwrap();
+                let pass = extract_string(&config, "password").unwrap();
+                let connect_str = config.url;
+                DbConnectionManager::Oracle(OracleConnectionManager::new(uname, pass, connect_str))
+            }
+            Some("sqlite") => DbConnectionManager::Sqlite(match config.url {
+                // every pooled connection has to see the same in-memory database
+                ":memory:" => "file:world_countries?mode=memory&cache=shared".to_owned(),
+                path => path.to_owned(),
+            }),
+            Some("postgres") => DbConnectionManager::Postgres(config.url.to_owned()),
+            Some(other) => {
+                return Err(Error::ConfigError {
+                    message: format!("unknown database backend {:?}", other),
+                })
+            }
+        };
+        Ok(r2d2::Pool::builder().max_size(20).build(manager).unwrap())
+    }
+}
+
+#[database("oracle_db")]
+pub struct OracleConnection(DbConnection);
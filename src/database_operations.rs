@@ -1,200 +1,1242 @@
-use crate::database_oracle::DbConnection;
+use crate::backend::{Backend, Row, RowValue, SqlValue};
+use crate::database::DbConnection;
+use crate::ddl::{Column, ColumnType};
 use crate::error::Error;
-use crate::schema::UserInfo;
+use crate::filter::{
+    keyset_condition, order_by, order_columns, where_clause, Condition, SortColumn,
+};
+use crate::password;
+use crate::schema::{
+    ApiToken, AuditEntry, LoginFailure, PasswordHistory, RecoveryCode, Role, RoleGrant, Session,
+    TrashEntry, UserInfo, UserRole, UserTotp,
+};
+use crate::tokens::random_string;
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
-use r2d2_oracle::oracle::{sql_type::ToSql, RowValue};
+use serde::Serialize;
+use serde_json::{Map, Value};
 use std::fmt::Debug;
 
 pub trait SchemaTable {
     fn column_names() -> Vec<&'static str>;
     fn table_name() -> &'static str;
-    fn values(&self) -> Vec<Box<dyn ToSql>>;
+    fn values(&self) -> Vec<SqlValue>;
     fn key_attrs() -> Vec<&'static str>;
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>>;
+    fn key_attr_values(&self) -> Vec<SqlValue>;
+    /// Types and constraints of `column_names`, in the same order.
+    fn columns() -> Vec<Column>;
 }
 
+fn query_as<T>(backend: &dyn Backend, sql: &str, params: &[SqlValue]) -> Result<Vec<T>, Error>
+where
+    T: RowValue,
+{
+    backend.query(sql, params)?.iter().map(T::get).collect()
+}
+
+/// Runs `f` and commits its changes, or rolls them back if it fails,
+/// so that a failed statement doesn't leave a transaction open on a pooled connection.
+/// Inside another transaction the changes are left to it to commit or roll back.
+fn in_transaction<R, F>(connection: &DbConnection, f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> Result<R, Error>,
+{
+    let depth = connection.enter_transaction();
+    let result = f();
+    if !depth.is_outermost() {
+        return result;
+    }
+    let backend = connection.backend();
+    match result {
+        Ok(result) => {
+            backend.commit()?;
+            Ok(result)
+        }
+        Err(e) => {
+            backend.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Runs `f` in a transaction, so that the changes of the functions it calls are committed
+/// together, or not at all if one of them fails.
+pub fn transaction<R, F>(connection: &DbConnection, f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> Result<R, Error>,
+{
+    in_transaction(connection, f)
+}
+
+fn key_condition<T>(backend: &dyn Backend, first_param: usize) -> String
+where
+    T: SchemaTable,
+{
+    T::key_attrs()
+        .into_iter()
+        .enumerate()
+        .map(|(i, key_attr_name)| {
+            format!("{}={}", key_attr_name, backend.placeholder(first_param + i))
+        })
+        .join(" and ")
+}
+
+/// `record_start` and `record_end` of the page `page_index`, counting pages from 1.
+pub fn page_records(page_index: usize, page_size: usize) -> Result<(usize, usize), Error> {
+    let invalid = || Error::InvalidPageError { page_index };
+    let record_end = page_index.checked_mul(page_size).ok_or_else(invalid)?;
+    match page_index {
+        0 => Err(invalid()),
+        _ => Ok((record_end - page_size + 1, record_end)),
+    }
+}
+
+/// Loads the rows `record_start..=record_end` of the rows matching `conditions`
+/// in the order given by `sort`.
 pub fn load_data<T>(
     connection: &DbConnection,
     record_start: usize,
     record_end: usize,
+    conditions: &[Condition],
+    sort: &[SortColumn],
 ) -> Result<Vec<T>, Error>
 where
     T: SchemaTable + RowValue + Debug,
 {
-    let conn = connection.oracle_connection();
+    let conn = connection.backend();
+    let (condition, mut sql_params) = where_clause(conn, conditions, 1);
     let sql = format!(
-        "select {} from (select c.*,rownum r from (select * from {} order by {}) c) where r between :1 and :2",
+        "select {} from {}{} order by {}",
         T::column_names().join(","),
         T::table_name(),
-        T::key_attrs().join(",")
+        condition,
+        order_by(&order_columns::<T>(sort))
     );
+    let (sql, page_params) = conn.paginate(&sql, sql_params.len() + 1, record_start, record_end);
+    sql_params.extend(page_params);
 
-    let conts = conn.query_as::<T>(&sql, &[&record_start, &record_end])?;
-    Ok(conts.collect::<Result<Vec<_>, _>>()?)
+    query_as::<T>(conn, &sql, &sql_params)
 }
 
-pub fn insert_data<T>(connection: &DbConnection, table_entity: &T) -> Result<(), Error>
+/// Loads up to `limit` rows matching `conditions` in the given `order`, starting after the row
+/// with the `after` values of the order columns, or from the first row.
+pub fn load_data_after<T>(
+    connection: &DbConnection,
+    conditions: &[Condition],
+    order: &[SortColumn],
+    after: Option<&[SqlValue]>,
+    limit: usize,
+) -> Result<Vec<T>, Error>
 where
-    T: SchemaTable,
+    T: SchemaTable + RowValue,
 {
-    let conn = connection.oracle_connection();
+    let conn = connection.backend();
+    let (mut condition, mut sql_params) = where_clause(conn, conditions, 1);
+    if let Some(values) = after {
+        let (keyset, keyset_params) = keyset_condition(conn, order, values, sql_params.len() + 1);
+        condition.push_str(if condition.is_empty() {
+            " where "
+        } else {
+            " and "
+        });
+        condition.push_str(&keyset);
+        sql_params.extend(keyset_params);
+    }
     let sql = format!(
-        "insert into {} values ({})",
+        "select {} from {}{} order by {}",
+        T::column_names().join(","),
         T::table_name(),
-        T::column_names()
-            .into_iter()
-            .enumerate()
-            .map(|(i, _)| format!(":{}", i + 1))
-            .join(",")
+        condition,
+        order_by(order)
     );
+    let (sql, page_params) = conn.paginate(&sql, sql_params.len() + 1, 1, limit);
+    sql_params.extend(page_params);
 
-    let vals = table_entity.values();
-    let sql_params = vals
-        .iter()
-        .map(|i| i.as_ref())
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
-    conn.execute(&sql, sql_params.as_ref())?;
-    conn.commit()?;
-    Ok(())
+    query_as::<T>(conn, &sql, &sql_params)
 }
 
-fn check_data_key_exists<T>(connection: &DbConnection, table_entity: &T) -> Result<bool, Error>
+/// Looks up the row with the same key as `table_entity`.
+pub fn get_data<T>(connection: &DbConnection, table_entity: &T) -> Result<Option<T>, Error>
 where
     T: SchemaTable + RowValue,
 {
-    let conn = connection.oracle_connection();
+    let conn = connection.backend();
     let sql = format!(
         "select {} from {} where {}",
-        T::key_attrs().join(","),
+        T::column_names().join(","),
         T::table_name(),
-        T::key_attrs()
+        key_condition::<T>(conn, 1)
+    );
+    let matches = query_as::<T>(conn, &sql, &table_entity.key_attr_values())?;
+    Ok(if matches.len() == 1 {
+        matches.into_iter().next()
+    } else {
+        None
+    })
+}
+
+/// The row as it was written, read back by key if the backend couldn't return it.
+fn stored_data<T>(
+    connection: &DbConnection,
+    returned: Option<Vec<Row>>,
+    table_entity: &T,
+) -> Result<T, Error>
+where
+    T: SchemaTable + RowValue,
+{
+    let stored = match returned {
+        Some(rows) => rows.iter().map(T::get).next().transpose()?,
+        None => get_data(connection, table_entity)?,
+    };
+    stored.ok_or_else(|| Error::KeyDoesntExistError {
+        table_name: T::table_name().to_owned(),
+    })
+}
+
+fn insert_statement<T>(backend: &dyn Backend) -> String
+where
+    T: SchemaTable,
+{
+    format!(
+        "insert into {} ({}) values ({})",
+        T::table_name(),
+        T::column_names().join(","),
+        T::column_names()
             .into_iter()
             .enumerate()
-            .map(|(i, key)| format!("{}=:{}", key, i + 1))
-            .join(" and ")
-    );
-    let vals = table_entity.key_attr_values();
-    let sql_params = vals
-        .iter()
-        .map(|i| i.as_ref())
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
-    let matches = conn.query_as::<T>(&sql, sql_params.as_ref())?;
-    Ok(matches.count() > 0)
+            .map(|(i, _)| backend.placeholder(i + 1))
+            .join(",")
+    )
 }
 
-pub fn update_data<T>(
+/// Key columns of a row of `T` given as JSON, as a JSON object like `row_key` in `audit_log`.
+pub fn json_key<T>(row: &Value) -> String
+where
+    T: SchemaTable,
+{
+    let key = T::key_attrs()
+        .into_iter()
+        .map(|column| (column.to_owned(), row[column].clone()))
+        .collect::<Map<String, Value>>();
+    Value::Object(key).to_string()
+}
+
+fn row_key<T>(row: &T) -> Result<String, Error>
+where
+    T: SchemaTable + Serialize,
+{
+    Ok(json_key::<T>(&serde_json::to_value(row)?))
+}
+
+/// Records the change of a row in `audit_log`, inside the transaction of the change.
+/// Changes without an actor are bookkeeping, like sessions, and aren't recorded.
+fn record_change<T>(
+    backend: &dyn Backend,
+    actor: Option<&str>,
+    operation: &str,
+    old: Option<&T>,
+    new: Option<&T>,
+) -> Result<(), Error>
+where
+    T: SchemaTable + Serialize,
+{
+    let actor = match actor {
+        Some(actor) => actor,
+        None => return Ok(()),
+    };
+    let keyed = match old.or(new) {
+        Some(row) => row,
+        None => return Ok(()),
+    };
+    let entry = AuditEntry {
+        entry_id: random_string(12),
+        actor: actor.to_owned(),
+        changed_at: Utc::now(),
+        table_name: T::table_name().to_owned(),
+        operation: operation.to_owned(),
+        row_key: row_key(keyed)?,
+        old_value: old.map(serde_json::to_string).transpose()?,
+        new_value: new.map(serde_json::to_string).transpose()?,
+    };
+    backend.execute(&insert_statement::<AuditEntry>(backend), &entry.values())?;
+    Ok(())
+}
+
+/// Inserts the row without committing, see `insert_data`.
+pub fn insert_row<T>(
+    connection: &DbConnection,
+    actor: Option<&str>,
+    table_entity: &T,
+) -> Result<T, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    let conn = connection.backend();
+    let sql = insert_statement::<T>(conn);
+    let returned = conn.execute_returning(&sql, &table_entity.values(), &T::column_names())?;
+    let stored = stored_data(connection, returned, table_entity)?;
+    record_change(conn, actor, "insert", None, Some(&stored))?;
+    Ok(stored)
+}
+
+/// Inserts the row and returns it as it was stored, including database defaults.
+/// `actor` is the user making the change, recorded in `audit_log`.
+pub fn insert_data<T>(
+    connection: &DbConnection,
+    actor: Option<&str>,
+    table_entity: &T,
+) -> Result<T, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    in_transaction(connection, || insert_row(connection, actor, table_entity))
+}
+
+/// Relative difference up to which floats are taken as unchanged, since they don't always
+/// come back exactly as they were written, e.g. from an Oracle `number` column.
+const FLOAT_TOLERANCE: f64 = 1e-9;
+
+/// Condition matching the row only while it's stored like `row`, numbering the
+/// bind parameters from `first_param`. Timestamps are compared by the backend, since they
+/// don't always read back the way they were written, e.g. as SQLite text from a migration,
+/// and floats within `FLOAT_TOLERANCE`.
+fn unchanged_condition<T>(
+    backend: &dyn Backend,
+    row: &T,
+    first_param: usize,
+) -> (String, Vec<SqlValue>)
+where
+    T: SchemaTable,
+{
+    let mut params = Vec::new();
+    let condition = T::columns()
+        .into_iter()
+        .zip(row.values())
+        .map(|(column, value)| {
+            if value == SqlValue::Null {
+                return format!("{} is null", column.name);
+            }
+            let tolerance = match value {
+                SqlValue::Float(value) => value.abs() * FLOAT_TOLERANCE,
+                _ => 0.0,
+            };
+            params.push(value);
+            let placeholder = backend.placeholder(first_param + params.len() - 1);
+            match column.column_type {
+                ColumnType::DateTime => backend.datetime_equals(column.name, &placeholder),
+                ColumnType::Float => {
+                    params.push(tolerance.into());
+                    format!(
+                        "abs({}-{})<={}",
+                        column.name,
+                        placeholder,
+                        backend.placeholder(first_param + params.len() - 1)
+                    )
+                }
+                _ => format!("{}={}", column.name, placeholder),
+            }
+        })
+        .join(" and ");
+    (condition, params)
+}
+
+/// Updates the row without committing, see `update_data`.
+pub fn update_row<T>(
     connection: &DbConnection,
+    actor: Option<&str>,
     table_entity_old: &T,
     table_entity_new: &T,
-) -> Result<(), Error>
+) -> Result<T, Error>
 where
-    T: SchemaTable + RowValue,
+    T: SchemaTable + RowValue + Serialize,
 {
-    // first check if an item with the old keys exists
-    if !check_data_key_exists(connection, table_entity_old)? {
-        // error if it doesn't
-        return Err(Error::KeyDoesntExistError {
-            table_name: T::table_name().to_owned(),
-        });
-    }
-
     let col_len = T::column_names().len();
-    let conn = connection.oracle_connection();
+    let conn = connection.backend();
+    let (condition, condition_params) = unchanged_condition(conn, table_entity_old, col_len + 1);
     let sql = format!(
         "update {} set {} where {}",
         T::table_name(),
         T::column_names()
             .into_iter()
             .enumerate()
-            .map(|(i, col_name)| format!("{}=:{}", col_name, i + 1))
+            .map(|(i, col_name)| format!("{}={}", col_name, conn.placeholder(i + 1)))
             .join(","),
-        T::key_attrs()
-            .into_iter()
-            .enumerate()
-            .map(|(i, key_attr_name)| format!("{}=:{}", key_attr_name, col_len + i + 1))
-            .join(" and ")
+        condition
     );
 
-    let new_vals = table_entity_new.values();
-    let old_keys = table_entity_old.key_attr_values();
-    let sql_params = new_vals
-        .iter()
-        .chain(old_keys.iter())
-        .map(|i| i.as_ref())
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
-    conn.execute(&sql, sql_params.as_ref())?;
-    conn.commit()?;
-    Ok(())
+    let sql_params = table_entity_new
+        .values()
+        .into_iter()
+        .chain(condition_params)
+        .collect::<Vec<_>>();
+    // the stored row rather than the client's copy of it
+    let before = match actor {
+        Some(_) => get_data(connection, table_entity_old)?,
+        None => None,
+    };
+    let returned = conn.execute_returning(&sql, &sql_params, &T::column_names())?;
+    if matches!(&returned, Some(rows) if rows.is_empty()) {
+        // someone else changed or deleted the row since the client read it
+        return Err(match get_data(connection, table_entity_old)? {
+            Some(current) => Error::ConflictError {
+                message: format!("the row of {} was changed meanwhile", T::table_name()),
+                current: Some(serde_json::to_value(current)?),
+            },
+            None => Error::KeyDoesntExistError {
+                table_name: T::table_name().to_owned(),
+            },
+        });
+    }
+    let stored = stored_data(connection, returned, table_entity_new)?;
+    record_change(conn, actor, "update", before.as_ref(), Some(&stored))?;
+    Ok(stored)
 }
 
-pub fn delete_data<T>(connection: &DbConnection, table_entity: &T) -> Result<(), Error>
+/// Updates the row if it's still stored like `table_entity_old` and returns it as it was
+/// stored, otherwise fails with a conflict that has the current row.
+/// `actor` is the user making the change, recorded in `audit_log`.
+pub fn update_data<T>(
+    connection: &DbConnection,
+    actor: Option<&str>,
+    table_entity_old: &T,
+    table_entity_new: &T,
+) -> Result<T, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    in_transaction(connection, || {
+        update_row(connection, actor, table_entity_old, table_entity_new)
+    })
+}
+
+fn delete_statement<T>(backend: &dyn Backend) -> String
 where
     T: SchemaTable,
 {
-    let conn = connection.oracle_connection();
-    let sql = format!(
+    format!(
         "delete from {} where {}",
         T::table_name(),
-        T::key_attrs()
-            .into_iter()
-            .enumerate()
-            .map(|(i, key_attr_name)| format!("{}=:{}", key_attr_name, i + 1))
-            .join(" and ")
+        key_condition::<T>(backend, 1)
+    )
+}
+
+/// Deletes the row without committing and returns it as it was stored
+/// if it was read for the audit log, see `delete_data`.
+fn delete_row<T>(
+    connection: &DbConnection,
+    actor: Option<&str>,
+    table_entity: &T,
+) -> Result<Option<T>, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    let conn = connection.backend();
+    let before = match actor {
+        Some(_) => get_data(connection, table_entity)?,
+        None => None,
+    };
+    conn.execute(
+        &delete_statement::<T>(conn),
+        &table_entity.key_attr_values(),
+    )?;
+    record_change(conn, actor, "delete", before.as_ref(), None)?;
+    Ok(before)
+}
+
+/// Deletes the row with the key of `table_entity`.
+/// `actor` is the user making the change, recorded in `audit_log`.
+pub fn delete_data<T>(
+    connection: &DbConnection,
+    actor: Option<&str>,
+    table_entity: &T,
+) -> Result<(), Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    in_transaction(connection, || {
+        delete_row(connection, actor, table_entity).map(|_| ())
+    })
+}
+
+/// Moves the row to the trash without committing, see `trash_data`.
+pub fn trash_row<T>(
+    connection: &DbConnection,
+    actor: &str,
+    table_entity: &T,
+) -> Result<TrashEntry, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    let conn = connection.backend();
+    let deleted = delete_row(connection, Some(actor), table_entity)?.ok_or_else(|| {
+        Error::KeyDoesntExistError {
+            table_name: T::table_name().to_owned(),
+        }
+    })?;
+    let entry = TrashEntry {
+        trash_id: random_string(12),
+        table_name: T::table_name().to_owned(),
+        row_key: row_key(&deleted)?,
+        row_value: serde_json::to_string(&deleted)?,
+        deleted_by: actor.to_owned(),
+        deleted_at: Utc::now(),
+    };
+    conn.execute(&insert_statement::<TrashEntry>(conn), &entry.values())?;
+    Ok(entry)
+}
+
+/// Deletes the row like `delete_data` and keeps it in `trash`, from where admins
+/// can restore it.
+pub fn trash_data<T>(
+    connection: &DbConnection,
+    actor: &str,
+    table_entity: &T,
+) -> Result<TrashEntry, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    in_transaction(connection, || trash_row(connection, actor, table_entity))
+}
+
+/// Inserts the row of a trash entry again and removes the entry.
+pub fn restore_data<T>(
+    connection: &DbConnection,
+    actor: &str,
+    entry: &TrashEntry,
+    table_entity: &T,
+) -> Result<T, Error>
+where
+    T: SchemaTable + RowValue + Serialize,
+{
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        let restored = insert_row(connection, Some(actor), table_entity)?;
+        conn.execute(
+            &delete_statement::<TrashEntry>(conn),
+            &entry.key_attr_values(),
+        )?;
+        Ok(restored)
+    })
+}
+
+/// Foreign keys of the row that point to rows which don't exist, as `table.column`.
+pub fn missing_references<T>(
+    connection: &DbConnection,
+    table_entity: &T,
+) -> Result<Vec<String>, Error>
+where
+    T: SchemaTable,
+{
+    let conn = connection.backend();
+    let mut missing = Vec::new();
+    for (column, value) in T::columns().into_iter().zip(table_entity.values()) {
+        let references = match column.references {
+            Some(references) if value != SqlValue::Null => references,
+            _ => continue,
+        };
+        let sql = format!(
+            "select count(*) from {} where {}={}",
+            references.table,
+            references.column,
+            conn.placeholder(1)
+        );
+        let count: usize = conn
+            .query(&sql, &[value])?
+            .pop()
+            .ok_or_else(|| Error::TableEmptyError {
+                table_name: references.table.to_owned(),
+            })?
+            .get_at(0)?;
+        if count == 0 {
+            missing.push(format!("{}.{}", references.table, references.column));
+        }
+    }
+    Ok(missing)
+}
+
+/// Audit entries of the table in the order they were written, only those written
+/// after `after` if it's given.
+pub fn get_audit_entries(
+    connection: &DbConnection,
+    table_name: &str,
+    after: Option<DateTime<Utc>>,
+) -> Result<Vec<AuditEntry>, Error> {
+    let conn = connection.backend();
+    let mut sql_params = vec![table_name.into()];
+    let mut condition = format!("table_name={}", conn.placeholder(1));
+    if let Some(after) = after {
+        condition.push_str(&format!(" and changed_at>{}", conn.placeholder(2)));
+        sql_params.push(after.into());
+    }
+    let sql = format!(
+        "select {} from audit_log where {} order by changed_at,entry_id",
+        AuditEntry::column_names().join(","),
+        condition
     );
+    query_as(conn, &sql, &sql_params)
+}
 
-    let vals = table_entity.key_attr_values();
-    let sql_params = vals
-        .iter()
-        .map(|i| i.as_ref())
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
-    conn.execute(&sql, sql_params.as_ref())?;
-    conn.commit()?;
-    Ok(())
+/// `like` pattern for the key columns of `key`, a `row_key` of `audit_log`, inside the JSON
+/// of a row. `!` escapes the wildcards.
+fn key_pattern<T>(key: &str) -> Result<String, Error>
+where
+    T: SchemaTable,
+{
+    let key = serde_json::from_str::<Value>(key)?;
+    let escape = |part: String| {
+        part.replace('!', "!!")
+            .replace('%', "!%")
+            .replace('_', "!_")
+    };
+    // key columns are serialized in the order of the columns
+    let parts = T::column_names()
+        .into_iter()
+        .filter(|column| T::key_attrs().contains(column))
+        .map(|column| escape(format!("{}:{}", Value::from(column), key[column])))
+        .collect::<Vec<_>>();
+    Ok(format!("%{}%", parts.join("%")))
+}
+
+/// Audit entries of the row with the key in the order they were written: its inserts,
+/// updates and deletes, and updates that changed another key to it. Only the entries
+/// written before `before` are returned if it's given.
+pub fn get_row_audit_entries<T>(
+    connection: &DbConnection,
+    row_key: &str,
+    before: Option<&AuditEntry>,
+) -> Result<Vec<AuditEntry>, Error>
+where
+    T: SchemaTable,
+{
+    let conn = connection.backend();
+    let mut sql_params = vec![
+        T::table_name().into(),
+        row_key.into(),
+        key_pattern::<T>(row_key)?.into(),
+    ];
+    let mut condition = format!(
+        "table_name={} and (row_key={} or (operation='update' and new_value like {} escape '!'))",
+        conn.placeholder(1),
+        conn.placeholder(2),
+        conn.placeholder(3)
+    );
+    if let Some(before) = before {
+        condition.push_str(&format!(
+            " and (changed_at<{} or (changed_at={} and entry_id<{}))",
+            conn.placeholder(4),
+            conn.placeholder(5),
+            conn.placeholder(6)
+        ));
+        sql_params.push(before.changed_at.into());
+        sql_params.push(before.changed_at.into());
+        sql_params.push(before.entry_id.as_str().into());
+    }
+    let sql = format!(
+        "select {} from audit_log where {} order by changed_at,entry_id",
+        AuditEntry::column_names().join(","),
+        condition
+    );
+    query_as(conn, &sql, &sql_params)
 }
 
-pub fn count_rows<T>(connection: &DbConnection) -> Result<usize, Error>
+/// Counts the rows matching `conditions`.
+pub fn count_rows<T>(connection: &DbConnection, conditions: &[Condition]) -> Result<usize, Error>
 where
     T: SchemaTable,
 {
-    let conn = connection.oracle_connection();
-    let sql = format!("select count(*) from {}", T::table_name());
+    let conn = connection.backend();
+    let (condition, sql_params) = where_clause(conn, conditions, 1);
+    let sql = format!("select count(*) from {}{}", T::table_name(), condition);
 
-    conn.query_as::<usize>(&sql, &[])?
-        .collect::<Result<Vec<_>, _>>()?
+    conn.query(&sql, &sql_params)?
         .pop()
         .ok_or_else(|| Error::TableEmptyError {
-            table_name: "continents".to_owned(),
-        })
+            table_name: T::table_name().to_owned(),
+        })?
+        .get_at(0)
 }
 
 pub fn get_user(connection: &DbConnection, user: &UserInfo) -> Result<Option<UserInfo>, Error> {
-    let conn = connection.oracle_connection();
+    get_data(connection, user)
+}
+
+/// Deletes the user along with their role assignments, API tokens, sessions,
+/// two-factor authentication and password history.
+pub fn delete_user(connection: &DbConnection, actor: &str, user: &UserInfo) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        let before = get_user(connection, user)?;
+        for table in &[
+            "user_roles",
+            "api_tokens",
+            "sessions",
+            "user_totp",
+            "recovery_codes",
+            "password_history",
+        ] {
+            conn.execute(
+                &format!(
+                    "delete from {} where username={}",
+                    table,
+                    conn.placeholder(1)
+                ),
+                &[user.username.as_str().into()],
+            )?;
+        }
+        conn.execute(&delete_statement::<UserInfo>(conn), &user.key_attr_values())?;
+        record_change(conn, Some(actor), "delete", before.as_ref(), None)
+    })
+}
+
+/// Previous passwords of the user, most recent first.
+pub fn get_password_history(
+    connection: &DbConnection,
+    username: &str,
+) -> Result<Vec<PasswordHistory>, Error> {
+    let conn = connection.backend();
     let sql = format!(
-        "select {} from {} where {}",
-        UserInfo::column_names().join(","),
-        UserInfo::table_name(),
-        UserInfo::key_attrs()
-            .into_iter()
-            .enumerate()
-            .map(|(i, key_attr_name)| format!("{}=:{}", key_attr_name, i + 1))
-            .join(" and ")
+        "select {} from password_history where username={} order by changed_at desc",
+        PasswordHistory::column_names().join(","),
+        conn.placeholder(1)
+    );
+    query_as(conn, &sql, &[username.into()])
+}
+
+/// Replaces the password of the user with the hash and keeps the `history_size`
+/// most recent previous passwords.
+pub fn change_password(
+    connection: &DbConnection,
+    user: &UserInfo,
+    new_hash: &str,
+    history_size: usize,
+) -> Result<UserInfo, Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        // plain text passwords from before hashing aren't kept
+        if history_size > 0 && !password::needs_rehash(&user.password) {
+            let previous = PasswordHistory {
+                username: user.username.clone(),
+                password: user.password.clone(),
+                changed_at: user.password_changed_at,
+            };
+            conn.execute(
+                &insert_statement::<PasswordHistory>(conn),
+                &previous.values(),
+            )?;
+        }
+        conn.execute(
+            &format!(
+                "update users_info set password={},password_changed_at={} where username={}",
+                conn.placeholder(1),
+                conn.placeholder(2),
+                conn.placeholder(3)
+            ),
+            &[
+                new_hash.into(),
+                Utc::now().into(),
+                user.username.as_str().into(),
+            ],
+        )?;
+        let sql = format!(
+            "select {} from password_history where username={} order by changed_at desc",
+            PasswordHistory::column_names().join(","),
+            conn.placeholder(1)
+        );
+        let history = query_as::<PasswordHistory>(conn, &sql, &[user.username.as_str().into()])?;
+        for old in history.iter().skip(history_size) {
+            conn.execute(
+                &delete_statement::<PasswordHistory>(conn),
+                &old.key_attr_values(),
+            )?;
+        }
+        Ok(())
+    })?;
+    get_user(connection, user)?.ok_or_else(|| Error::KeyDoesntExistError {
+        table_name: UserInfo::table_name().to_owned(),
+    })
+}
+
+/// Role that makes users admins, see `auth::Admin`.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Names of the roles of the user.
+pub fn get_user_roles(connection: &DbConnection, username: &str) -> Result<Vec<String>, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select role_name from user_roles where username={} order by role_name",
+        conn.placeholder(1)
     );
-    let vals = user.key_attr_values();
-    let sql_params = vals
+    conn.query(&sql, &[username.into()])?
         .iter()
-        .map(|i| i.as_ref())
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
-    let matches = conn
-        .query_as::<UserInfo>(&sql, sql_params.as_ref())?
-        .collect::<Result<Vec<_>, _>>()?;
-    Ok(if matches.len() == 1 {
-        Some(matches.into_iter().next().take().unwrap())
-    } else {
-        None
+        .map(|row| row.get_at(0))
+        .collect()
+}
+
+/// Whether the user has the admin role.
+pub fn is_admin(connection: &DbConnection, username: &str) -> Result<bool, Error> {
+    Ok(get_user_roles(connection, username)?
+        .iter()
+        .any(|role| role == ADMIN_ROLE))
+}
+
+/// Replaces the roles of the user. `users_info.is_admin` is kept in step with the admin role.
+pub fn set_user_roles(
+    connection: &DbConnection,
+    username: &str,
+    roles: &[String],
+) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        conn.execute(
+            &format!(
+                "delete from user_roles where username={}",
+                conn.placeholder(1)
+            ),
+            &[username.into()],
+        )?;
+        for role in roles {
+            let user_role = UserRole {
+                username: username.to_owned(),
+                role_name: role.clone(),
+            };
+            conn.execute(&insert_statement::<UserRole>(conn), &user_role.values())?;
+        }
+        let is_admin = roles.iter().any(|role| role == ADMIN_ROLE);
+        conn.execute(
+            &format!(
+                "update users_info set is_admin={} where username={}",
+                conn.placeholder(1),
+                conn.placeholder(2)
+            ),
+            &[if is_admin { "y" } else { "n" }.into(), username.into()],
+        )?;
+        Ok(())
+    })
+}
+
+/// API tokens of the user, newest first.
+pub fn get_user_tokens(connection: &DbConnection, username: &str) -> Result<Vec<ApiToken>, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select {} from api_tokens where username={} order by created_at desc",
+        ApiToken::column_names().join(","),
+        conn.placeholder(1)
+    );
+    query_as(conn, &sql, &[username.into()])
+}
+
+/// The API token with the hash, whether it has expired or not.
+pub fn get_token_by_hash(
+    connection: &DbConnection,
+    token_hash: &str,
+) -> Result<Option<ApiToken>, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select {} from api_tokens where token_hash={}",
+        ApiToken::column_names().join(","),
+        conn.placeholder(1)
+    );
+    Ok(query_as(conn, &sql, &[token_hash.into()])?
+        .into_iter()
+        .next())
+}
+
+/// Sessions of the user, or of all users, most recently used first.
+pub fn get_sessions(
+    connection: &DbConnection,
+    username: Option<&str>,
+) -> Result<Vec<Session>, Error> {
+    let conn = connection.backend();
+    let (condition, params) = match username {
+        Some(username) => (
+            format!(" where username={}", conn.placeholder(1)),
+            vec![username.into()],
+        ),
+        None => (String::new(), Vec::new()),
+    };
+    let sql = format!(
+        "select {} from sessions{} order by last_seen desc",
+        Session::column_names().join(","),
+        condition
+    );
+    query_as(conn, &sql, &params)
+}
+
+/// Signs the user out everywhere.
+pub fn delete_user_sessions(connection: &DbConnection, username: &str) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        conn.execute(
+            &format!(
+                "delete from sessions where username={}",
+                conn.placeholder(1)
+            ),
+            &[username.into()],
+        )?;
+        Ok(())
+    })
+}
+
+/// Unused recovery codes of the user.
+pub fn get_recovery_codes(
+    connection: &DbConnection,
+    username: &str,
+) -> Result<Vec<RecoveryCode>, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select {} from recovery_codes where username={}",
+        RecoveryCode::column_names().join(","),
+        conn.placeholder(1)
+    );
+    query_as(conn, &sql, &[username.into()])
+}
+
+/// Replaces the recovery codes of the user.
+pub fn set_recovery_codes(
+    connection: &DbConnection,
+    username: &str,
+    codes: &[RecoveryCode],
+) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        conn.execute(
+            &format!(
+                "delete from recovery_codes where username={}",
+                conn.placeholder(1)
+            ),
+            &[username.into()],
+        )?;
+        for code in codes {
+            conn.execute(&insert_statement::<RecoveryCode>(conn), &code.values())?;
+        }
+        Ok(())
+    })
+}
+
+/// Confirms the TOTP secret with a code of time step `step` and records the step, unless
+/// a code of the same or a later step was accepted first. Returns whether it was recorded.
+pub fn use_totp_step(connection: &DbConnection, totp: &UserTotp, step: i64) -> Result<bool, Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        let changed = conn.execute(
+            &format!(
+                "update user_totp set is_confirmed='y', last_step={} \
+                 where username={} and secret={} and (last_step is null or last_step<{})",
+                conn.placeholder(1),
+                conn.placeholder(2),
+                conn.placeholder(3),
+                conn.placeholder(4)
+            ),
+            &[
+                step.into(),
+                totp.username.as_str().into(),
+                totp.secret.as_str().into(),
+                step.into(),
+            ],
+        )?;
+        Ok(changed > 0)
+    })
+}
+
+/// Deletes the recovery code and returns whether it was there, so that a code used by
+/// concurrent logins is only accepted once.
+pub fn use_recovery_code(connection: &DbConnection, code: &RecoveryCode) -> Result<bool, Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        let deleted = conn.execute(
+            &delete_statement::<RecoveryCode>(conn),
+            &code.key_attr_values(),
+        )?;
+        Ok(deleted == 1)
+    })
+}
+
+/// Adds a failure to the row of the subject in one statement, so that concurrent failures
+/// are all counted, and returns the row as it is then. The row stays locked by the
+/// transaction, `None` means that the subject has no row yet.
+pub fn add_login_failure(
+    connection: &DbConnection,
+    failure: &LoginFailure,
+) -> Result<Option<LoginFailure>, Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        let changed = conn.execute(
+            &format!(
+                "update login_failures set failures=failures+1 where {}",
+                key_condition::<LoginFailure>(conn, 1)
+            ),
+            &failure.key_attr_values(),
+        )?;
+        match changed {
+            0 => Ok(None),
+            _ => get_data(connection, failure),
+        }
+    })
+}
+
+/// Turns off two-factor authentication of the user.
+pub fn delete_two_factor(connection: &DbConnection, username: &str) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        for table in &["recovery_codes", "user_totp"] {
+            conn.execute(
+                &format!(
+                    "delete from {} where username={}",
+                    table,
+                    conn.placeholder(1)
+                ),
+                &[username.into()],
+            )?;
+        }
+        Ok(())
     })
 }
+
+/// Whether one of the roles of the user allows `operation` on `table`.
+pub fn has_grant(
+    connection: &DbConnection,
+    username: &str,
+    table: &str,
+    operation: &str,
+) -> Result<bool, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select count(*) from role_grants g join user_roles u on u.role_name=g.role_name \
+         where u.username={} and g.operation={} and (g.table_name={} or g.table_name='*')",
+        conn.placeholder(1),
+        conn.placeholder(2),
+        conn.placeholder(3)
+    );
+    let count: i64 =
+        conn.query(&sql, &[username.into(), operation.into(), table.into()])?[0].get_at(0)?;
+    Ok(count > 0)
+}
+
+/// Operations the roles of the user allow on `table`.
+pub fn get_granted_operations(
+    connection: &DbConnection,
+    username: &str,
+    table: &str,
+) -> Result<Vec<String>, Error> {
+    let conn = connection.backend();
+    let sql = format!(
+        "select distinct g.operation from role_grants g join user_roles u \
+         on u.role_name=g.role_name where u.username={} and (g.table_name={} or g.table_name='*')",
+        conn.placeholder(1),
+        conn.placeholder(2)
+    );
+    conn.query(&sql, &[username.into(), table.into()])?
+        .iter()
+        .map(|row| row.get_at(0))
+        .collect()
+}
+
+/// All roles with their grants.
+pub fn get_roles(connection: &DbConnection) -> Result<Vec<(Role, Vec<RoleGrant>)>, Error> {
+    let conn = connection.backend();
+    let roles = query_as::<Role>(conn, "select name from roles order by name", &[])?;
+    let grants = query_as::<RoleGrant>(
+        conn,
+        "select role_name,table_name,operation from role_grants \
+         order by role_name,table_name,operation",
+        &[],
+    )?;
+    Ok(roles
+        .into_iter()
+        .map(|role| {
+            let role_grants = grants
+                .iter()
+                .filter(|g| g.role_name == role.name)
+                .cloned()
+                .collect();
+            (role, role_grants)
+        })
+        .collect())
+}
+
+/// Creates the role if it doesn't exist and replaces its grants.
+pub fn set_role_grants(
+    connection: &DbConnection,
+    role: &Role,
+    grants: &[RoleGrant],
+) -> Result<(), Error> {
+    let conn = connection.backend();
+    let exists = get_data(connection, role)?.is_some();
+    in_transaction(connection, || {
+        if !exists {
+            conn.execute(&insert_statement::<Role>(conn), &role.values())?;
+        }
+        conn.execute(
+            &format!(
+                "delete from role_grants where role_name={}",
+                conn.placeholder(1)
+            ),
+            &[role.name.as_str().into()],
+        )?;
+        for grant in grants {
+            conn.execute(&insert_statement::<RoleGrant>(conn), &grant.values())?;
+        }
+        Ok(())
+    })
+}
+
+/// Deletes the role, its grants and its assignments to users.
+pub fn delete_role(connection: &DbConnection, role: &Role) -> Result<(), Error> {
+    let conn = connection.backend();
+    in_transaction(connection, || {
+        for table in &["user_roles", "role_grants"] {
+            conn.execute(
+                &format!(
+                    "delete from {} where role_name={}",
+                    table,
+                    conn.placeholder(1)
+                ),
+                &[role.name.as_str().into()],
+            )?;
+        }
+        conn.execute(&delete_statement::<Role>(conn), &role.key_attr_values())?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Continent;
+    use crate::test_database;
+    use chrono::Duration;
+
+    fn user(username: &str) -> UserInfo {
+        UserInfo {
+            username: username.to_owned(),
+            password: "hash".to_owned(),
+            is_admin: "n".to_owned(),
+            ..UserInfo::default()
+        }
+    }
+
+    /// Changes only the timestamp of a row, after which an update of the row as it was
+    /// read before has to fail.
+    fn check_datetime_conflict(connection: &DbConnection) {
+        let read = insert_data(connection, None, &user("alice")).unwrap();
+        let changed = UserInfo {
+            last_appearance: read.last_appearance + Duration::seconds(1),
+            ..read.clone()
+        };
+        update_data(connection, None, &read, &changed).unwrap();
+        let stale = UserInfo {
+            is_disabled: "y".to_owned(),
+            ..read.clone()
+        };
+        match update_data(connection, None, &read, &stale) {
+            Err(Error::ConflictError { .. }) => {}
+            result => panic!("expected a conflict, got {:?}", result),
+        }
+        // the row as it's stored now, with the precision of the database
+        let current = get_user(connection, &read).unwrap().unwrap();
+        update_data(connection, None, &current, &stale).unwrap();
+    }
+
+    #[test]
+    fn sqlite_datetime_change_conflicts() {
+        check_datetime_conflict(&test_database::sqlite());
+    }
+
+    #[test]
+    fn postgres_datetime_change_conflicts() {
+        if let Some(connection) = test_database::postgres() {
+            check_datetime_conflict(&connection);
+        }
+    }
+
+    /// Updates a row from a copy whose float differs from the stored one in the last digits,
+    /// like after a round trip through JSON, and then from a copy with another value.
+    fn check_float_tolerance(connection: &DbConnection) {
+        let stored = insert_data(
+            connection,
+            None,
+            &Continent {
+                name: "Atlantis".to_owned(),
+                area_m2: 0.1 + 0.2,
+            },
+        )
+        .unwrap();
+        let read = Continent {
+            area_m2: 0.3,
+            ..stored.clone()
+        };
+        let changed = Continent {
+            area_m2: 1.5,
+            ..stored.clone()
+        };
+        update_data(connection, None, &read, &changed).unwrap();
+        match update_data(connection, None, &read, &stored) {
+            Err(Error::ConflictError { .. }) => {}
+            result => panic!("expected a conflict, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sqlite_float_round_trip_doesnt_conflict() {
+        check_float_tolerance(&test_database::sqlite());
+    }
+
+    #[test]
+    fn postgres_float_round_trip_doesnt_conflict() {
+        if let Some(connection) = test_database::postgres() {
+            check_float_tolerance(&connection);
+        }
+    }
+
+    #[test]
+    fn sqlite_timestamps_written_by_migrations_match() {
+        let connection = test_database::sqlite();
+        connection
+            .backend()
+            .execute(
+                "insert into users_info (username, password, is_admin) values ('bob','hash','n')",
+                &[],
+            )
+            .unwrap();
+        let read = get_user(&connection, &user("bob")).unwrap().unwrap();
+        let new = UserInfo {
+            is_disabled: "y".to_owned(),
+            ..read.clone()
+        };
+        assert_eq!(
+            update_data(&connection, None, &read, &new)
+                .unwrap()
+                .is_disabled,
+            "y"
+        );
+    }
+
+    #[test]
+    fn postgres_update_returns_the_stored_row() {
+        let connection = match test_database::postgres() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let read = insert_data(&*connection, None, &user("alice")).unwrap();
+        let new = UserInfo {
+            is_disabled: "y".to_owned(),
+            ..read.clone()
+        };
+        let stored = update_data(&*connection, None, &read, &new).unwrap();
+        assert_eq!(stored.is_disabled, "y");
+        // postgres keeps microseconds
+        assert_eq!(stored.last_appearance.timestamp_subsec_nanos() % 1000, 0);
+
+        let backend = connection.backend();
+        let update = "update users_info set is_disabled=$1 where username=$2";
+        let returned = backend
+            .execute_returning(update, &["n".into(), "alice".into()], &["is_disabled"])
+            .unwrap();
+        let is_disabled = returned.unwrap()[0].get::<String>("is_disabled").unwrap();
+        assert_eq!(is_disabled, "n");
+        let returned = backend
+            .execute_returning(update, &["n".into(), "bob".into()], &["is_disabled"])
+            .unwrap();
+        assert_eq!(returned.map(|rows| rows.len()), Some(0));
+    }
+}
@@ -1,68 +1,141 @@
-use r2d2_oracle::{oracle, r2d2, OracleConnectionManager};
-use rocket::config::Value;
-use rocket_contrib::databases::{DatabaseConfig, Poolable};
+use crate::backend::{Backend, Dialect, Row, SqlValue};
+use crate::error::Error;
+use chrono::{DateTime, Utc};
+use r2d2_oracle::oracle::{
+    self,
+    sql_type::{OracleType, ToSql},
+};
 
-pub struct DbConnectionManager(OracleConnectionManager);
+pub struct OracleBackend(oracle::Connection);
 
-impl DbConnectionManager {
-    pub fn new(username: &str, password: &str, connect_string: &str) -> DbConnectionManager {
-        DbConnectionManager(OracleConnectionManager::new(
-            username,
-            password,
-            connect_string,
-        ))
+impl OracleBackend {
+    pub fn new(connection: oracle::Connection) -> Self {
+        OracleBackend(connection)
     }
 }
 
-impl r2d2::ManageConnection for DbConnectionManager {
-    type Connection = DbConnection;
-    type Error = oracle::Error;
+fn to_oracle(value: &SqlValue) -> Box<dyn ToSql> {
+    match value {
+        SqlValue::Null => Box::new(None::<String>),
+        SqlValue::Int(v) => Box::new(*v),
+        SqlValue::Float(v) => Box::new(*v),
+        SqlValue::Text(v) => Box::new(v.clone()),
+        SqlValue::DateTime(v) => Box::new(*v),
+    }
+}
+
+fn from_oracle(
+    row: &oracle::Row,
+    index: usize,
+    oracle_type: &OracleType,
+) -> Result<SqlValue, oracle::Error> {
+    Ok(match oracle_type {
+        // NUMBER columns hold both the ids and the areas, so let the value decide
+        OracleType::Number(..) | OracleType::Int64 | OracleType::UInt64 => {
+            match row.get::<usize, Option<String>>(index)? {
+                Some(v) => v
+                    .parse::<i64>()
+                    .map(SqlValue::Int)
+                    .unwrap_or_else(|_| SqlValue::Float(v.parse().unwrap_or(std::f64::NAN))),
+                None => SqlValue::Null,
+            }
+        }
+        OracleType::Float(_) | OracleType::BinaryFloat | OracleType::BinaryDouble => row
+            .get::<usize, Option<f64>>(index)?
+            .map_or(SqlValue::Null, SqlValue::Float),
+        OracleType::Date
+        | OracleType::Timestamp(_)
+        | OracleType::TimestampTZ(_)
+        | OracleType::TimestampLTZ(_) => row
+            .get::<usize, Option<DateTime<Utc>>>(index)?
+            .map_or(SqlValue::Null, SqlValue::DateTime),
+        _ => row
+            .get::<usize, Option<String>>(index)?
+            .map_or(SqlValue::Null, SqlValue::Text),
+    })
+}
 
-    fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        self.0.connect().map(DbConnection)
+impl Backend for OracleBackend {
+    fn dialect(&self) -> Dialect {
+        Dialect::Oracle
     }
 
-    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        self.0.is_valid(&mut conn.0)
+    fn placeholder(&self, index: usize) -> String {
+        format!(":{}", index)
     }
 
-    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-        self.0.has_broken(&mut conn.0)
+    fn paginate(
+        &self,
+        sql: &str,
+        first_param: usize,
+        record_start: usize,
+        record_end: usize,
+    ) -> (String, Vec<SqlValue>) {
+        (
+            format!(
+                "select * from (select c.*,rownum r from ({}) c) where r between {} and {}",
+                sql,
+                self.placeholder(first_param),
+                self.placeholder(first_param + 1)
+            ),
+            vec![record_start.into(), record_end.into()],
+        )
     }
-}
 
-pub struct DbConnection(oracle::Connection);
+    fn query(&self, sql: &str, params: &[SqlValue]) -> Result<Vec<Row>, Error> {
+        let vals = params.iter().map(to_oracle).collect::<Vec<_>>();
+        let sql_params = vals.iter().map(|i| i.as_ref()).collect::<Vec<_>>();
+        let rows = self.0.query(sql, &sql_params)?;
+        let columns = rows
+            .column_info()
+            .iter()
+            .map(|c| c.name().to_owned())
+            .collect::<Vec<_>>();
+        let types = rows
+            .column_info()
+            .iter()
+            .map(|c| c.oracle_type().clone())
+            .collect::<Vec<_>>();
+        rows.map(|row| {
+            let row = row?;
+            let values = types
+                .iter()
+                .enumerate()
+                .map(|(i, oracle_type)| from_oracle(&row, i, oracle_type))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Row::new(columns.clone(), values))
+        })
+        .collect()
+    }
 
-impl DbConnection {
-    pub fn oracle_connection(&self) -> &oracle::Connection {
-        &self.0
+    fn execute(&self, sql: &str, params: &[SqlValue]) -> Result<u64, Error> {
+        let vals = params.iter().map(to_oracle).collect::<Vec<_>>();
+        let sql_params = vals.iter().map(|i| i.as_ref()).collect::<Vec<_>>();
+        Ok(self.0.execute(sql, &sql_params)?.row_count()?)
     }
-}
 
-impl Poolable for DbConnection {
-    type Manager = DbConnectionManager;
-    type Error = oracle::Error;
+    /// The bound time has nanoseconds and a time zone, so it's converted like it is when
+    /// it's stored in a `timestamp` column.
+    fn datetime_equals(&self, column: &str, placeholder: &str) -> String {
+        format!("{}=cast({} as timestamp)", column, placeholder)
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        Ok(self.0.commit()?)
+    }
+
+    fn rollback(&self) -> Result<(), Error> {
+        Ok(self.0.rollback()?)
+    }
+
+    fn ping(&self) -> Result<(), Error> {
+        Ok(self.0.ping()?)
+    }
 
-    fn pool(config: DatabaseConfig) -> Result<r2d2::Pool<Self::Manager>, Self::Error> {
-        fn extract_string<'a>(
-            config: &'a DatabaseConfig,
-            extra_name: &'static str,
-        ) -> Option<&'a String> {
-            config.extras.get(extra_name).and_then(|v| {
-                if let Value::String(sv) = v {
-                    Some(sv)
-                } else {
-                    None
-                }
-            })
+    fn has_broken(&self) -> bool {
+        match self.0.status() {
+            Ok(oracle::ConnStatus::Normal) => false,
+            _ => true,
         }
-        let uname = extract_string(&config, "username").unwrap();
-        let pass = extract_string(&config, "password").unwrap();
-        let connect_str = config.url;
-        let manager = DbConnectionManager::new(uname, pass, connect_str);
-        Ok(r2d2::Pool::builder().max_size(20).build(manager).unwrap())
     }
 }
-
-#[database("oracle_db")]
-pub struct OracleConnection(DbConnection);
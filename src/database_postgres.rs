@@ -0,0 +1,206 @@
+use crate::backend::{Backend, Dialect, Row, SqlValue};
+use crate::error::Error;
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use postgres::types::{to_sql_checked, IsNull, ToSql, Type};
+use postgres::{Client, NoTls};
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+
+/// PostgreSQL connection.
+///
+/// Like with SQLite, a transaction is opened before the first change and kept until
+/// `commit` or `rollback`, so statements behave the same as on Oracle.
+pub struct PostgresBackend {
+    client: RefCell<Client>,
+    in_transaction: Cell<bool>,
+}
+
+impl PostgresBackend {
+    pub fn connect(params: &str) -> Result<Self, Error> {
+        Ok(PostgresBackend {
+            client: RefCell::new(Client::connect(params, NoTls)?),
+            in_transaction: Cell::new(false),
+        })
+    }
+
+    fn begin_if_needed(&self) -> Result<(), Error> {
+        if !self.in_transaction.get() {
+            self.client.borrow_mut().batch_execute("begin")?;
+            self.in_transaction.set(true);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, statement: &str) -> Result<(), Error> {
+        if self.in_transaction.replace(false) {
+            self.client.borrow_mut().batch_execute(statement)?;
+        }
+        Ok(())
+    }
+
+    fn run_query(&self, sql: &str, params: &[SqlValue]) -> Result<Vec<Row>, Error> {
+        let values = params.iter().map(PgValue).collect::<Vec<_>>();
+        let sql_params = values
+            .iter()
+            .map(|v| v as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        let rows = self.client.borrow_mut().query(sql, &sql_params)?;
+        rows.iter()
+            .map(|row| {
+                let columns = row
+                    .columns()
+                    .iter()
+                    .map(|c| c.name().to_owned())
+                    .collect::<Vec<_>>();
+                let values = (0..columns.len())
+                    .map(|i| from_postgres(row, i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Row::new(columns, values))
+            })
+            .collect()
+    }
+}
+
+/// Binds a `SqlValue` to whatever type the server expects for the parameter,
+/// since postgres doesn't convert e.g. a bigint parameter to an integer column.
+#[derive(Debug)]
+struct PgValue<'a>(&'a SqlValue);
+
+impl ToSql for PgValue<'_> {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match self.0 {
+            SqlValue::Null => Ok(IsNull::Yes),
+            SqlValue::Int(v) => match *ty {
+                Type::INT2 => i16::try_from(*v)?.to_sql(ty, out),
+                Type::INT4 => i32::try_from(*v)?.to_sql(ty, out),
+                Type::FLOAT4 => (*v as f32).to_sql(ty, out),
+                Type::FLOAT8 => (*v as f64).to_sql(ty, out),
+                _ => v.to_sql(ty, out),
+            },
+            SqlValue::Float(v) => match *ty {
+                Type::FLOAT4 => (*v as f32).to_sql(ty, out),
+                _ => v.to_sql(ty, out),
+            },
+            SqlValue::Text(v) => v.to_sql(ty, out),
+            SqlValue::DateTime(v) => match *ty {
+                Type::TIMESTAMP => v.naive_utc().to_sql(ty, out),
+                _ => v.to_sql(ty, out),
+            },
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn from_postgres(row: &postgres::Row, index: usize) -> Result<SqlValue, postgres::Error> {
+    Ok(match *row.columns()[index].type_() {
+        Type::INT2 => row
+            .try_get::<_, Option<i16>>(index)?
+            .map_or(SqlValue::Null, |v| SqlValue::Int(v.into())),
+        Type::INT4 => row
+            .try_get::<_, Option<i32>>(index)?
+            .map_or(SqlValue::Null, |v| SqlValue::Int(v.into())),
+        Type::INT8 => row
+            .try_get::<_, Option<i64>>(index)?
+            .map_or(SqlValue::Null, SqlValue::Int),
+        Type::FLOAT4 => row
+            .try_get::<_, Option<f32>>(index)?
+            .map_or(SqlValue::Null, |v| SqlValue::Float(v.into())),
+        Type::FLOAT8 => row
+            .try_get::<_, Option<f64>>(index)?
+            .map_or(SqlValue::Null, SqlValue::Float),
+        Type::TIMESTAMPTZ => row
+            .try_get::<_, Option<DateTime<Utc>>>(index)?
+            .map_or(SqlValue::Null, SqlValue::DateTime),
+        Type::TIMESTAMP => row
+            .try_get::<_, Option<NaiveDateTime>>(index)?
+            .map_or(SqlValue::Null, |v| {
+                SqlValue::DateTime(Utc.from_utc_datetime(&v))
+            }),
+        _ => row
+            .try_get::<_, Option<String>>(index)?
+            .map_or(SqlValue::Null, SqlValue::Text),
+    })
+}
+
+impl Backend for PostgresBackend {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        format!("${}", index)
+    }
+
+    fn paginate(
+        &self,
+        sql: &str,
+        first_param: usize,
+        record_start: usize,
+        record_end: usize,
+    ) -> (String, Vec<SqlValue>) {
+        (
+            format!(
+                "{} limit {} offset {}",
+                sql,
+                self.placeholder(first_param),
+                self.placeholder(first_param + 1)
+            ),
+            vec![
+                (record_end + 1).saturating_sub(record_start).into(),
+                record_start.saturating_sub(1).into(),
+            ],
+        )
+    }
+
+    fn query(&self, sql: &str, params: &[SqlValue]) -> Result<Vec<Row>, Error> {
+        self.run_query(sql, params)
+    }
+
+    fn execute(&self, sql: &str, params: &[SqlValue]) -> Result<u64, Error> {
+        self.begin_if_needed()?;
+        let values = params.iter().map(PgValue).collect::<Vec<_>>();
+        let sql_params = values
+            .iter()
+            .map(|v| v as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        Ok(self.client.borrow_mut().execute(sql, &sql_params)?)
+    }
+
+    fn execute_returning(
+        &self,
+        sql: &str,
+        params: &[SqlValue],
+        columns: &[&str],
+    ) -> Result<Option<Vec<Row>>, Error> {
+        self.begin_if_needed()?;
+        let sql = format!("{} returning {}", sql, columns.join(","));
+        self.run_query(&sql, params).map(Some)
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        self.finish("commit")
+    }
+
+    fn rollback(&self) -> Result<(), Error> {
+        self.finish("rollback")
+    }
+
+    fn ping(&self) -> Result<(), Error> {
+        self.client.borrow_mut().simple_query("")?;
+        Ok(())
+    }
+
+    fn has_broken(&self) -> bool {
+        self.client.borrow().is_closed()
+    }
+}
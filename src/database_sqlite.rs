@@ -0,0 +1,132 @@
+use crate::backend::{Backend, Dialect, Row, SqlValue};
+use crate::error::Error;
+use rusqlite::types::{Value, ValueRef};
+
+/// SQLite connection for local development.
+///
+/// SQLite commits every statement on its own, so a transaction is opened before the
+/// first change and kept until `commit` or `rollback`, the same way Oracle does it.
+pub struct SqliteBackend(rusqlite::Connection);
+
+impl SqliteBackend {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch("pragma foreign_keys = on")?;
+        Ok(SqliteBackend(connection))
+    }
+
+    fn begin_if_needed(&self) -> Result<(), Error> {
+        if self.0.is_autocommit() {
+            self.0.execute_batch("begin")?;
+        }
+        Ok(())
+    }
+}
+
+fn to_sqlite(value: &SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Int(v) => Value::Integer(*v),
+        SqlValue::Float(v) => Value::Real(*v),
+        SqlValue::Text(v) => Value::Text(v.clone()),
+        // stored as text, FromSqlValue parses it back
+        SqlValue::DateTime(v) => Value::Text(v.to_rfc3339()),
+    }
+}
+
+fn from_sqlite(value: ValueRef) -> SqlValue {
+    match value {
+        ValueRef::Null => SqlValue::Null,
+        ValueRef::Integer(v) => SqlValue::Int(v),
+        ValueRef::Real(v) => SqlValue::Float(v),
+        ValueRef::Text(v) | ValueRef::Blob(v) => {
+            SqlValue::Text(String::from_utf8_lossy(v).into_owned())
+        }
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        format!("?{}", index)
+    }
+
+    fn paginate(
+        &self,
+        sql: &str,
+        first_param: usize,
+        record_start: usize,
+        record_end: usize,
+    ) -> (String, Vec<SqlValue>) {
+        (
+            format!(
+                "{} limit {} offset {}",
+                sql,
+                self.placeholder(first_param),
+                self.placeholder(first_param + 1)
+            ),
+            vec![
+                (record_end + 1).saturating_sub(record_start).into(),
+                record_start.saturating_sub(1).into(),
+            ],
+        )
+    }
+
+    fn query(&self, sql: &str, params: &[SqlValue]) -> Result<Vec<Row>, Error> {
+        let mut stmt = self.0.prepare(sql)?;
+        let columns = stmt
+            .column_names()
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let mut rows = stmt.query(params.iter().map(to_sqlite))?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|i| row.get_raw(i))
+                .map(from_sqlite)
+                .collect();
+            result.push(Row::new(columns.clone(), values));
+        }
+        Ok(result)
+    }
+
+    fn execute(&self, sql: &str, params: &[SqlValue]) -> Result<u64, Error> {
+        self.begin_if_needed()?;
+        Ok(self.0.execute(sql, params.iter().map(to_sqlite))? as u64)
+    }
+
+    /// Timestamps are text, written as RFC 3339 with nanoseconds and an offset here but
+    /// with a `Z` by the migrations, so both sides are compared as UTC milliseconds.
+    fn datetime_equals(&self, column: &str, placeholder: &str) -> String {
+        format!(
+            "strftime('%Y-%m-%d %H:%M:%f',{})=strftime('%Y-%m-%d %H:%M:%f',{})",
+            column, placeholder
+        )
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        if !self.0.is_autocommit() {
+            self.0.execute_batch("commit")?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<(), Error> {
+        if !self.0.is_autocommit() {
+            self.0.execute_batch("rollback")?;
+        }
+        Ok(())
+    }
+
+    fn ping(&self) -> Result<(), Error> {
+        Ok(self.0.execute_batch("select 1")?)
+    }
+
+    fn has_broken(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,496 @@
+//! Table definitions generated from the `SchemaTable` metadata of the schema structs.
+
+use crate::backend::Dialect;
+use crate::database_operations::SchemaTable;
+use crate::error::Error;
+use crate::schema::{
+    ApiToken, AuditEntry, City, Continent, Country, District, LoginFailure, PasswordHistory,
+    RecoveryCode, Region, Role, RoleGrant, Session, TrashEntry, UserInfo, UserRole, UserTotp,
+};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    /// `i32` fields, which are `integer` rather than `bigint` on Postgres.
+    Integer32,
+    Float,
+    Text,
+    DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub table: &'static str,
+    pub column: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnDefault {
+    /// SQL of a value that is the same for every dialect, like `'n'`.
+    Value(&'static str),
+    /// The time the row is inserted.
+    Now,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+    /// Length of a text column from `#[column(len = ...)]`, `TEXT_LENGTH` if it isn't given.
+    pub length: Option<u32>,
+    /// Digits of an Oracle integer column from `#[column(digits = ...)]`, `INTEGER_DIGITS`
+    /// if they aren't given.
+    pub digits: Option<u32>,
+    /// `char` instead of `varchar`, for flags like `is_admin`.
+    pub fixed_length: bool,
+    pub default: Option<ColumnDefault>,
+    /// SQL of the default on SQLite instead of `default`, for columns that SQLite migrations
+    /// could only add with a constant default.
+    pub sqlite_default: Option<&'static str>,
+    pub unique: bool,
+    pub references: Option<ForeignKey>,
+}
+
+/// Length of text columns, like names, which is enough for most of them.
+pub const TEXT_LENGTH: u32 = 100;
+
+/// Digits of integer columns on Oracle, which is enough for ids.
+pub const INTEGER_DIGITS: u32 = 10;
+
+/// Column type of a field, `Option` fields are nullable.
+pub trait SqlType {
+    const COLUMN_TYPE: ColumnType;
+    const NULLABLE: bool = false;
+}
+
+impl SqlType for String {
+    const COLUMN_TYPE: ColumnType = ColumnType::Text;
+}
+
+impl SqlType for i32 {
+    const COLUMN_TYPE: ColumnType = ColumnType::Integer32;
+}
+
+impl SqlType for i64 {
+    const COLUMN_TYPE: ColumnType = ColumnType::Integer;
+}
+
+impl SqlType for usize {
+    const COLUMN_TYPE: ColumnType = ColumnType::Integer;
+}
+
+impl SqlType for f64 {
+    const COLUMN_TYPE: ColumnType = ColumnType::Float;
+}
+
+impl SqlType for DateTime<Utc> {
+    const COLUMN_TYPE: ColumnType = ColumnType::DateTime;
+}
+
+impl<T: SqlType> SqlType for Option<T> {
+    const COLUMN_TYPE: ColumnType = T::COLUMN_TYPE;
+    const NULLABLE: bool = true;
+}
+
+fn type_name(dialect: Dialect, column: &Column) -> String {
+    let length = column.length.unwrap_or(TEXT_LENGTH);
+    match (dialect, column.column_type) {
+        (Dialect::Oracle, ColumnType::Integer) | (Dialect::Oracle, ColumnType::Integer32) => {
+            format!("number({})", column.digits.unwrap_or(INTEGER_DIGITS))
+        }
+        (Dialect::Oracle, ColumnType::Float) => "number".to_owned(),
+        (Dialect::Oracle, ColumnType::Text) if column.fixed_length => format!("char({})", length),
+        (Dialect::Oracle, ColumnType::Text) => format!("varchar2({})", length),
+        (Dialect::Oracle, ColumnType::DateTime) => "timestamp".to_owned(),
+        (Dialect::Sqlite, ColumnType::Integer) | (Dialect::Sqlite, ColumnType::Integer32) => {
+            "integer".to_owned()
+        }
+        (Dialect::Sqlite, ColumnType::Float) => "real".to_owned(),
+        // datetimes are stored as RFC 3339 text
+        (Dialect::Sqlite, ColumnType::Text) | (Dialect::Sqlite, ColumnType::DateTime) => {
+            "text".to_owned()
+        }
+        (Dialect::Postgres, ColumnType::Integer) => "bigint".to_owned(),
+        (Dialect::Postgres, ColumnType::Integer32) => "integer".to_owned(),
+        (Dialect::Postgres, ColumnType::Float) => "double precision".to_owned(),
+        (Dialect::Postgres, ColumnType::Text) if column.fixed_length => format!("char({})", length),
+        (Dialect::Postgres, ColumnType::Text) => format!("varchar({})", length),
+        (Dialect::Postgres, ColumnType::DateTime) => "timestamptz".to_owned(),
+    }
+}
+
+fn default_sql(dialect: Dialect, column: &Column) -> Option<&'static str> {
+    match (dialect, column.sqlite_default, column.default?) {
+        (Dialect::Sqlite, Some(sql), _) | (_, _, ColumnDefault::Value(sql)) => Some(sql),
+        (Dialect::Oracle, _, ColumnDefault::Now) => Some("systimestamp"),
+        (Dialect::Sqlite, _, ColumnDefault::Now) => Some("(strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))"),
+        (Dialect::Postgres, _, ColumnDefault::Now) => Some("now()"),
+    }
+}
+
+fn foreign_key_name(table: &str, column: &str) -> String {
+    format!("{}_{}_fk", table, column)
+}
+
+/// `create table` statement of `T`.
+///
+/// A single key column is declared `primary key` inline like in the migrations, which on
+/// SQLite doesn't make it `not null` unless it's an integer.
+/// SQLite can't add constraints to an existing table, so there foreign keys are declared
+/// inline. It only checks them when rows change, so they may point to tables created later.
+pub fn create_table<T: SchemaTable>(dialect: Dialect) -> String {
+    let single_key = match T::key_attrs().as_slice() {
+        [key] => Some(*key),
+        _ => None,
+    };
+    let mut definitions = T::columns()
+        .iter()
+        .map(|c| {
+            let mut definition = format!("{} {}", c.name, type_name(dialect, c));
+            if let Some(default) = default_sql(dialect, c) {
+                definition.push_str(&format!(" default {}", default));
+            }
+            if single_key == Some(c.name) {
+                definition.push_str(" primary key");
+            } else if !c.nullable {
+                definition.push_str(" not null");
+            }
+            if c.unique {
+                definition.push_str(" unique");
+            }
+            if let (Dialect::Sqlite, Some(fk)) = (dialect, c.references) {
+                definition.push_str(&format!(" references {} ({})", fk.table, fk.column));
+            }
+            definition
+        })
+        .collect::<Vec<_>>();
+    if single_key.is_none() {
+        definitions.push(format!(
+            "constraint {}_pk primary key ({})",
+            T::table_name(),
+            T::key_attrs().join(", ")
+        ));
+    }
+    format!(
+        "create table {} (\n    {}\n)",
+        T::table_name(),
+        definitions.join(",\n    ")
+    )
+}
+
+/// Statements adding the foreign keys of `T`, empty for SQLite where they are declared inline.
+pub fn foreign_keys<T: SchemaTable>(dialect: Dialect) -> Vec<String> {
+    if dialect == Dialect::Sqlite {
+        return Vec::new();
+    }
+    T::columns()
+        .iter()
+        .filter_map(|c| c.references.map(|fk| (c.name, fk)))
+        .map(|(column, fk)| {
+            format!(
+                "alter table {} add constraint {} foreign key ({}) references {} ({})",
+                T::table_name(),
+                foreign_key_name(T::table_name(), column),
+                column,
+                fk.table,
+                fk.column
+            )
+        })
+        .collect()
+}
+
+/// The whole schema: all tables first, then the foreign keys between them,
+/// since the tables reference each other in a cycle.
+pub fn schema(dialect: Dialect) -> Vec<String> {
+    vec![
+        create_table::<Continent>(dialect),
+        create_table::<Country>(dialect),
+        create_table::<Region>(dialect),
+        create_table::<City>(dialect),
+        create_table::<District>(dialect),
+        create_table::<UserInfo>(dialect),
+        create_table::<Role>(dialect),
+        create_table::<RoleGrant>(dialect),
+        create_table::<UserRole>(dialect),
+        create_table::<ApiToken>(dialect),
+        create_table::<Session>(dialect),
+        create_table::<LoginFailure>(dialect),
+        create_table::<UserTotp>(dialect),
+        create_table::<RecoveryCode>(dialect),
+        create_table::<PasswordHistory>(dialect),
+        create_table::<AuditEntry>(dialect),
+        create_table::<TrashEntry>(dialect),
+    ]
+    .into_iter()
+    .chain(foreign_keys::<Country>(dialect))
+    .chain(foreign_keys::<Region>(dialect))
+    .chain(foreign_keys::<City>(dialect))
+    .chain(foreign_keys::<District>(dialect))
+    .chain(foreign_keys::<RoleGrant>(dialect))
+    .chain(foreign_keys::<UserRole>(dialect))
+    .chain(foreign_keys::<ApiToken>(dialect))
+    .chain(foreign_keys::<Session>(dialect))
+    .chain(foreign_keys::<UserTotp>(dialect))
+    .chain(foreign_keys::<RecoveryCode>(dialect))
+    .chain(foreign_keys::<PasswordHistory>(dialect))
+    .collect()
+}
+
+/// Prints the schema DDL for `schema <oracle|sqlite|postgres>`.
+pub fn command(args: &[String]) -> Result<(), Error> {
+    let dialect = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["oracle"] => Dialect::Oracle,
+        ["sqlite"] => Dialect::Sqlite,
+        ["postgres"] => Dialect::Postgres,
+        _ => {
+            return Err(Error::ConfigError {
+                message: "usage: schema oracle | schema sqlite | schema postgres".to_owned(),
+            })
+        }
+    };
+    println!("{};", schema(dialect).join(";\n\n"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DbConnection;
+    use crate::migrations::{migrations, Migration};
+    use crate::test_database;
+
+    fn create_schema(connection: &DbConnection, dialect: Dialect) {
+        let backend = connection.backend();
+        for statement in schema(dialect) {
+            backend.execute(&statement, &[]).unwrap();
+        }
+        backend.commit().unwrap();
+    }
+
+    /// Columns, foreign keys and unique constraints of the tables, one line each.
+    fn describe_sqlite(connection: &DbConnection) -> Vec<String> {
+        let query = |sql: &str| connection.backend().query(sql, &[]).unwrap();
+        let text = |row: &crate::backend::Row, column: &str| row.get::<String>(column).unwrap();
+        let mut lines = Vec::new();
+        let tables = query(
+            "select name from sqlite_master \
+             where type = 'table' and name <> 'schema_migrations'",
+        );
+        for table in tables.iter().map(|row| text(row, "name")) {
+            for column in query(&format!("pragma table_info({})", table)) {
+                let default = column.get::<Option<String>>("dflt_value").unwrap();
+                lines.push(format!(
+                    "{}.{} {}{}{}{}",
+                    table,
+                    text(&column, "name"),
+                    text(&column, "type").to_lowercase(),
+                    if column.get::<i64>("notnull").unwrap() > 0 {
+                        " not null"
+                    } else {
+                        ""
+                    },
+                    default.map_or_else(String::new, |default| format!(" default {}", default)),
+                    if column.get::<i64>("pk").unwrap() > 0 {
+                        " key"
+                    } else {
+                        ""
+                    },
+                ));
+            }
+            for fk in query(&format!("pragma foreign_key_list({})", table)) {
+                lines.push(format!(
+                    "{}.{} references {}.{}",
+                    table,
+                    text(&fk, "from"),
+                    text(&fk, "table"),
+                    text(&fk, "to")
+                ));
+            }
+            for index in query(&format!("pragma index_list({})", table)) {
+                if index.get::<i64>("unique").unwrap() == 0 || text(&index, "origin") != "u" {
+                    continue;
+                }
+                let columns = query(&format!("pragma index_info({})", text(&index, "name")))
+                    .iter()
+                    .map(|column| text(column, "name"))
+                    .collect::<Vec<_>>();
+                lines.push(format!("{} unique ({})", table, columns.join(", ")));
+            }
+        }
+        lines.sort();
+        lines
+    }
+
+    /// Columns and constraints of the tables in the current schema, one line each.
+    fn describe_postgres(connection: &DbConnection) -> Vec<String> {
+        let query = |sql: &str| connection.backend().query(sql, &[]).unwrap();
+        let text = |row: &crate::backend::Row, column: &str| row.get::<String>(column).unwrap();
+        let mut lines = Vec::new();
+        let columns = query(
+            "select table_name::text as table_name, column_name::text as column_name, \
+             data_type::text as data_type, character_maximum_length::integer as length, \
+             is_nullable::text as is_nullable, column_default::text as column_default \
+             from information_schema.columns \
+             where table_schema = current_schema() and table_name <> 'schema_migrations'",
+        );
+        for column in columns {
+            let length = column.get::<Option<i64>>("length").unwrap();
+            let default = column.get::<Option<String>>("column_default").unwrap();
+            lines.push(format!(
+                "{}.{} {}{}{}{}",
+                text(&column, "table_name"),
+                text(&column, "column_name"),
+                text(&column, "data_type"),
+                length.map_or_else(String::new, |length| format!("({})", length)),
+                if text(&column, "is_nullable") == "NO" {
+                    " not null"
+                } else {
+                    ""
+                },
+                default.map_or_else(String::new, |default| format!(" default {}", default)),
+            ));
+        }
+        let constraints = query(
+            "select tc.constraint_type::text as constraint_type, \
+             kcu.table_name::text as table_name, kcu.column_name::text as column_name, \
+             ccu.table_name::text as foreign_table, ccu.column_name::text as foreign_column \
+             from information_schema.table_constraints tc \
+             join information_schema.key_column_usage kcu \
+             on kcu.constraint_schema = tc.constraint_schema \
+             and kcu.constraint_name = tc.constraint_name \
+             left join information_schema.constraint_column_usage ccu \
+             on tc.constraint_type = 'FOREIGN KEY' \
+             and ccu.constraint_schema = tc.constraint_schema \
+             and ccu.constraint_name = tc.constraint_name \
+             where tc.table_schema = current_schema() and tc.table_name <> 'schema_migrations'",
+        );
+        for constraint in constraints {
+            let foreign_table = constraint.get::<Option<String>>("foreign_table").unwrap();
+            let foreign_column = constraint.get::<Option<String>>("foreign_column").unwrap();
+            lines.push(format!(
+                "{}.{} {}{}",
+                text(&constraint, "table_name"),
+                text(&constraint, "column_name"),
+                text(&constraint, "constraint_type").to_lowercase(),
+                match (foreign_table, foreign_column) {
+                    (Some(table), Some(column)) => format!(" {}.{}", table, column),
+                    _ => String::new(),
+                }
+            ));
+        }
+        lines.sort();
+        lines
+    }
+
+    /// Columns, keys and foreign keys declared by Oracle `create table` and `alter table`
+    /// statements, one line each. Foreign keys are declared inline or added afterwards, so
+    /// they get lines of their own without the constraint names.
+    fn describe_oracle(statements: &[String]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for statement in statements {
+            let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+            if let Some(create) = statement.strip_prefix("create table ") {
+                let (table, definitions) = create.split_at(create.find(" (").unwrap());
+                let definitions = definitions[2..].strip_suffix(')').unwrap();
+                for definition in split_definitions(definitions) {
+                    describe_oracle_definition(table, definition, &mut lines);
+                }
+            } else if let Some(alter) = statement.strip_prefix("alter table ") {
+                let (table, change) = alter.split_at(alter.find(' ').unwrap());
+                let change = change.trim_start().strip_prefix("add ").unwrap();
+                let definition = match change.strip_prefix('(') {
+                    Some(column) => column.strip_suffix(')').unwrap(),
+                    None => change,
+                };
+                describe_oracle_definition(table, definition, &mut lines);
+            }
+        }
+        lines.sort();
+        lines
+    }
+
+    /// The definitions of a `create table` statement, split at the commas between them.
+    fn split_definitions(definitions: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in definitions.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(definitions[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(definitions[start..].trim());
+        parts
+    }
+
+    fn describe_oracle_definition(table: &str, definition: &str, lines: &mut Vec<String>) {
+        if let Some(constraint) = definition.strip_prefix("constraint ") {
+            let constraint = &constraint[constraint.find(' ').unwrap() + 1..];
+            if let Some(foreign_key) = constraint.strip_prefix("foreign key (") {
+                let (column, target) = foreign_key.split_at(foreign_key.find(')').unwrap());
+                let target = target.strip_prefix(") references ").unwrap();
+                lines.push(format!("{}.{} references {}", table, column, target));
+            } else {
+                lines.push(format!("{} {}", table, constraint));
+            }
+            return;
+        }
+        let (column, target) = match definition.find(" references ") {
+            Some(i) => (
+                &definition[..i],
+                Some(&definition[i + " references ".len()..]),
+            ),
+            None => (definition, None),
+        };
+        lines.push(format!("{}.{}", table, column));
+        if let Some(target) = target {
+            let name = column.split(' ').next().unwrap();
+            lines.push(format!("{}.{} references {}", table, name, target));
+        }
+    }
+
+    #[test]
+    fn oracle_schema_matches_migrations() {
+        let migrated = migrations(Dialect::Oracle)
+            .iter()
+            .flat_map(Migration::up_statements)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            describe_oracle(&schema(Dialect::Oracle)),
+            describe_oracle(&migrated)
+        );
+    }
+
+    #[test]
+    fn sqlite_schema_matches_migrations() {
+        let generated = test_database::empty_sqlite();
+        create_schema(&generated, Dialect::Sqlite);
+        assert_eq!(
+            describe_sqlite(&generated),
+            describe_sqlite(&test_database::sqlite())
+        );
+    }
+
+    #[test]
+    fn postgres_schema_matches_migrations() {
+        let (generated, migrated) =
+            match (test_database::empty_postgres(), test_database::postgres()) {
+                (Some(generated), Some(migrated)) => (generated, migrated),
+                _ => return,
+            };
+        create_schema(&generated, Dialect::Postgres);
+        assert_eq!(describe_postgres(&generated), describe_postgres(&migrated));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::backend::SqlValue;
 use r2d2_oracle::oracle;
 use rocket::{
     http::{ContentType, Status},
@@ -5,13 +6,33 @@ use rocket::{
     response::Responder,
     Request, Response,
 };
+use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
     OracleError(r2d2_oracle::oracle::Error),
+    SqliteError(rusqlite::Error),
+    PostgresError(postgres::Error),
     TableEmptyError { table_name: String },
     KeyDoesntExistError { table_name: String },
     TableDoesntExistError { table: String },
+    ColumnDoesntExistError { column: String },
+    ColumnTypeError { column: String, value: SqlValue },
+    ConfigError { message: String },
+    InvalidItemError(serde_json::Error),
+    MigrationError { migration: String, message: String },
+    InvalidFilterError { filter: String, reason: String },
+    InvalidSortError { sort: String, reason: String },
+    InvalidCursorError { reason: String },
+    InvalidPageError { page_index: usize },
+    InvalidPageSizeError { page_size: usize, max_page_size: usize },
+    InvalidDateError { value: String },
+    PasswordHashError(argon2::Error),
+    UserManagementError { message: String },
+    /// The change doesn't fit the rows as they are now, `current` is the row if there is one.
+    ConflictError { message: String, current: Option<serde_json::Value> },
+    ForbiddenError { table: String, operation: String },
+    InvalidBatchError { reason: String },
 }
 
 impl From<r2d2_oracle::oracle::Error> for Error {
@@ -20,6 +41,112 @@ impl From<r2d2_oracle::oracle::Error> for Error {
     }
 }
 
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::SqliteError(error)
+    }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(error: postgres::Error) -> Self {
+        Error::PostgresError(error)
+    }
+}
+
+impl From<argon2::Error> for Error {
+    fn from(error: argon2::Error) -> Self {
+        Error::PasswordHashError(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::InvalidItemError(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OracleError(or_error) => write!(f, "{}", or_error),
+            Error::SqliteError(sqlite_error) => write!(f, "{}", sqlite_error),
+            Error::PostgresError(pg_error) => write!(f, "{}", pg_error),
+            Error::TableEmptyError { table_name } => write!(f, "table {} is empty", table_name),
+            Error::KeyDoesntExistError { table_name } => {
+                write!(f, "key doesn't exist in table {}", table_name)
+            }
+            Error::TableDoesntExistError { table } => write!(f, "table {} doesn't exist", table),
+            Error::ColumnDoesntExistError { column } => {
+                write!(f, "column {} doesn't exist", column)
+            }
+            Error::ColumnTypeError { column, value } => {
+                write!(f, "column {} has unexpected value {:?}", column, value)
+            }
+            Error::ConfigError { message } => write!(f, "{}", message),
+            Error::InvalidItemError(json_error) => write!(f, "invalid item: {}", json_error),
+            Error::MigrationError { migration, message } => {
+                write!(f, "migration {} failed: {}", migration, message)
+            }
+            Error::InvalidFilterError { filter, reason } => {
+                write!(f, "invalid filter {:?}: {}", filter, reason)
+            }
+            Error::InvalidSortError { sort, reason } => {
+                write!(f, "invalid sort {}: {}", sort, reason)
+            }
+            Error::InvalidCursorError { reason } => write!(f, "invalid cursor: {}", reason),
+            Error::InvalidPageError { page_index } => write!(f, "invalid page {}", page_index),
+            Error::InvalidPageSizeError {
+                page_size,
+                max_page_size,
+            } => write!(f, "page size {} is over {}", page_size, max_page_size),
+            Error::InvalidDateError { value } => write!(f, "invalid date {:?}", value),
+            Error::PasswordHashError(argon2_error) => write!(f, "{}", argon2_error),
+            Error::UserManagementError { message } => write!(f, "{}", message),
+            Error::ConflictError { message, .. } => write!(f, "{}", message),
+            Error::ForbiddenError { table, operation } => {
+                write!(f, "{} isn't allowed on table {}", operation, table)
+            }
+            Error::InvalidBatchError { reason } => write!(f, "invalid batch: {}", reason),
+        }
+    }
+}
+
+impl Error {
+    /// Whether a statement failed because a row with the same key or unique value exists.
+    pub fn is_unique_violation(&self) -> bool {
+        match self {
+            // ORA-00001: unique constraint violated
+            Error::OracleError(oracle::Error::OciError(db_error)) => db_error.code() == 1,
+            Error::SqliteError(rusqlite::Error::SqliteFailure(sqlite_error, _)) => matches!(
+                sqlite_error.extended_code,
+                rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+                    | rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+            ),
+            Error::PostgresError(pg_error) => {
+                pg_error.code() == Some(&postgres::error::SqlState::UNIQUE_VIOLATION)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a statement failed because a row it references doesn't exist.
+    pub fn is_foreign_key_violation(&self) -> bool {
+        match self {
+            // ORA-02291: integrity constraint violated - parent key not found
+            Error::OracleError(oracle::Error::OciError(db_error)) => db_error.code() == 2291,
+            Error::SqliteError(rusqlite::Error::SqliteFailure(sqlite_error, _)) => {
+                sqlite_error.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY
+            }
+            Error::PostgresError(pg_error) => {
+                pg_error.code() == Some(&postgres::error::SqlState::FOREIGN_KEY_VIOLATION)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl<'r> Responder<'r> for Error {
     fn respond_to(self, req: &Request) -> response::Result<'r> {
         match self {
@@ -35,6 +162,37 @@ impl<'r> Responder<'r> for Error {
                     Err(Status::new(500, "oracle"))
                 }
             }
+            Error::SqliteError(sqlite_error) => match sqlite_error {
+                rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error {
+                        code: rusqlite::ErrorCode::ConstraintViolation,
+                        ..
+                    },
+                    _,
+                ) => Response::build_from(
+                    json!({ "error_msg": format!("{}", sqlite_error) }).respond_to(req)?,
+                )
+                .status(Status::new(278, "A constraint was violated"))
+                .header(ContentType::JSON)
+                .ok(),
+                _ => Err(Status::new(500, "sqlite")),
+            },
+            Error::PostgresError(pg_error) => {
+                // class 23 is "integrity constraint violation"
+                if pg_error
+                    .code()
+                    .map_or(false, |code| code.code().starts_with("23"))
+                {
+                    Response::build_from(
+                        json!({ "error_msg": format!("{}", pg_error) }).respond_to(req)?,
+                    )
+                    .status(Status::new(278, "A constraint was violated"))
+                    .header(ContentType::JSON)
+                    .ok()
+                } else {
+                    Err(Status::new(500, "postgres"))
+                }
+            }
             Error::TableEmptyError { .. } => Err(Status::new(500, "oracle")),
             Error::KeyDoesntExistError { .. } => Err(Status::new(
                 400,
@@ -43,6 +201,32 @@ impl<'r> Responder<'r> for Error {
             Error::TableDoesntExistError { .. } => {
                 Err(Status::new(400, "Specified table doesn't exist"))
             }
+            Error::InvalidFilterError { .. } => Err(Status::new(400, "Invalid filter")),
+            Error::InvalidSortError { .. } => Err(Status::new(400, "Invalid sort")),
+            Error::InvalidCursorError { .. } => Err(Status::new(400, "Invalid cursor")),
+            Error::InvalidPageError { .. } => Err(Status::new(400, "Invalid page")),
+            Error::InvalidPageSizeError { .. } => Err(Status::new(400, "Page size too large")),
+            Error::InvalidDateError { .. } => Err(Status::new(400, "Invalid date")),
+            Error::InvalidBatchError { .. } => Err(Status::new(400, "Invalid batch")),
+            Error::ForbiddenError { .. } => Err(Status::Forbidden),
+            Error::UserManagementError { message } => {
+                Response::build_from(json!({ "error_msg": message }).respond_to(req)?)
+                    .status(Status::BadRequest)
+                    .header(ContentType::JSON)
+                    .ok()
+            }
+            Error::ConflictError { message, current } => Response::build_from(
+                json!({ "error_msg": message, "current": current }).respond_to(req)?,
+            )
+            .status(Status::Conflict)
+            .header(ContentType::JSON)
+            .ok(),
+            Error::ColumnDoesntExistError { .. }
+            | Error::ColumnTypeError { .. }
+            | Error::ConfigError { .. }
+            | Error::MigrationError { .. }
+            | Error::PasswordHashError(_) => Err(Status::InternalServerError),
+            Error::InvalidItemError(_) => Err(Status::UnprocessableEntity),
         }
     }
 }
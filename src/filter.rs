@@ -0,0 +1,275 @@
+//! Row filters and sorting given as query parameters of the items endpoint.
+//!
+//! Every filter is a column name directly followed by an operator and a url encoded value:
+//! `population>1000000`, `fg_country_name=France`, `city_name~Lon%25`, `fg_region_id:null`.
+//! The supported operators are `=`, `!=`, `<`, `<=`, `>`, `>=`, `~` (`like`, text columns only)
+//! and `:null` / `:notnull`.
+//!
+//! Sorting is given by `sort_field` and `sort_order` (`asc` or `desc`), with several
+//! columns separated by commas: `sort_field=population,region_name&sort_order=desc`.
+
+use crate::backend::{Backend, SqlValue};
+use crate::database_operations::SchemaTable;
+use crate::ddl::ColumnType;
+use crate::error::Error;
+use chrono::{DateTime, Utc};
+use rocket::http::RawStr;
+use rocket::request::{FromQuery, Query};
+use std::convert::Infallible;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Like,
+    IsNull,
+    IsNotNull,
+}
+
+impl Operator {
+    /// Longer operators come first so that `>=` isn't read as `>`.
+    const SYNTAX: [(&'static str, Operator); 9] = [
+        (":notnull", Operator::IsNotNull),
+        (":null", Operator::IsNull),
+        ("!=", Operator::NotEqual),
+        ("<=", Operator::LessOrEqual),
+        (">=", Operator::GreaterOrEqual),
+        ("=", Operator::Equal),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+        ("~", Operator::Like),
+    ];
+
+    fn sql(self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Like => " like ",
+            Operator::IsNull => " is null",
+            Operator::IsNotNull => " is not null",
+        }
+    }
+}
+
+/// Query items that weren't matched by other parameters of the route,
+/// they are validated against a table by `conditions`.
+#[derive(Debug, Default)]
+pub struct FilterQuery(Vec<String>);
+
+impl<'q> FromQuery<'q> for FilterQuery {
+    type Error = Infallible;
+
+    fn from_query(query: Query<'q>) -> Result<Self, Self::Error> {
+        Ok(FilterQuery(
+            query.map(|item| item.raw.as_str().to_owned()).collect(),
+        ))
+    }
+}
+
+/// A validated filter with the value converted to the column type.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub column: &'static str,
+    pub operator: Operator,
+    pub value: SqlValue,
+}
+
+/// The value of a filter or a key in a url as a value of the column type.
+pub fn parse_value(column_type: ColumnType, value: &str) -> Option<SqlValue> {
+    match column_type {
+        ColumnType::Integer => value.parse::<i64>().ok().map(SqlValue::Int),
+        ColumnType::Integer32 => value.parse::<i32>().ok().map(SqlValue::from),
+        ColumnType::Float => value.parse::<f64>().ok().map(SqlValue::Float),
+        ColumnType::Text => Some(SqlValue::Text(value.to_owned())),
+        ColumnType::DateTime => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|v| SqlValue::DateTime(v.with_timezone(&Utc))),
+    }
+}
+
+fn condition<T: SchemaTable>(filter: &str) -> Result<Condition, Error> {
+    let invalid = |reason: &str| Error::InvalidFilterError {
+        filter: filter.to_owned(),
+        reason: reason.to_owned(),
+    };
+    // operators are never part of a column name, and are url encoded inside values
+    let split = filter
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or_else(|| filter.len());
+    let (name, rest) = filter.split_at(split);
+    let column = T::columns()
+        .into_iter()
+        .find(|c| c.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| invalid("unknown column"))?;
+    let (syntax, operator) = Operator::SYNTAX
+        .iter()
+        .find(|(syntax, _)| rest.starts_with(syntax))
+        .ok_or_else(|| invalid("unknown operator"))?;
+    let raw_value = &rest[syntax.len()..];
+
+    let value = match operator {
+        Operator::IsNull | Operator::IsNotNull if raw_value.is_empty() => SqlValue::Null,
+        Operator::IsNull | Operator::IsNotNull => return Err(invalid("unexpected value")),
+        Operator::Like if column.column_type != ColumnType::Text => {
+            return Err(invalid("like only works on text columns"))
+        }
+        _ => {
+            let decoded = RawStr::from_str(raw_value).url_decode_lossy();
+            parse_value(column.column_type, &decoded)
+                .ok_or_else(|| invalid("value doesn't match the column type"))?
+        }
+    };
+    Ok(Condition {
+        column: column.name,
+        operator: *operator,
+        value,
+    })
+}
+
+/// Validates the filters against the columns of `T`.
+pub fn conditions<T: SchemaTable>(filters: &FilterQuery) -> Result<Vec<Condition>, Error> {
+    filters.0.iter().map(|f| condition::<T>(f)).collect()
+}
+
+/// `where` clause joining the conditions with `and`, or an empty string if there are none.
+/// Bind parameters are numbered from `first_param`.
+pub fn where_clause(
+    backend: &dyn Backend,
+    conditions: &[Condition],
+    first_param: usize,
+) -> (String, Vec<SqlValue>) {
+    if conditions.is_empty() {
+        return (String::new(), Vec::new());
+    }
+    let mut params = Vec::new();
+    let sql = conditions
+        .iter()
+        .map(|c| match c.operator {
+            Operator::IsNull | Operator::IsNotNull => format!("{}{}", c.column, c.operator.sql()),
+            _ => {
+                params.push(c.value.clone());
+                format!(
+                    "{}{}{}",
+                    c.column,
+                    c.operator.sql(),
+                    backend.placeholder(first_param + params.len() - 1)
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" and ");
+    (format!(" where {}", sql), params)
+}
+
+/// Condition selecting the rows that come after the row with `values` of the `order` columns,
+/// which mustn't be null. Bind parameters are numbered from `first_param`.
+pub fn keyset_condition(
+    backend: &dyn Backend,
+    order: &[SortColumn],
+    values: &[SqlValue],
+    first_param: usize,
+) -> (String, Vec<SqlValue>) {
+    let mut params = Vec::new();
+    let mut alternatives = Vec::new();
+    // (a > :1) or (a = :2 and b > :3) or ...
+    for (i, last) in order.iter().enumerate() {
+        let mut comparisons = Vec::new();
+        for (column, value) in order[..i].iter().zip(values) {
+            params.push(value.clone());
+            comparisons.push(format!(
+                "{}={}",
+                column.column,
+                backend.placeholder(first_param + params.len() - 1)
+            ));
+        }
+        params.push(values[i].clone());
+        comparisons.push(format!(
+            "{}{}{}",
+            last.column,
+            if last.descending { "<" } else { ">" },
+            backend.placeholder(first_param + params.len() - 1)
+        ));
+        alternatives.push(format!("({})", comparisons.join(" and ")));
+    }
+    (format!("({})", alternatives.join(" or ")), params)
+}
+
+/// Unvalidated `sort_field` and `sort_order` parameters.
+#[derive(Debug, Default)]
+pub struct SortQuery {
+    pub fields: Option<String>,
+    pub orders: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortColumn {
+    pub column: &'static str,
+    pub descending: bool,
+}
+
+/// Validates the sort columns against the columns of `T`.
+/// Columns without a matching `sort_order` are sorted in ascending order.
+pub fn sort_columns<T: SchemaTable>(sort: &SortQuery) -> Result<Vec<SortColumn>, Error> {
+    fn split(list: &Option<String>) -> Vec<&str> {
+        list.as_ref()
+            .map_or_else(Vec::new, |l| l.split(',').map(str::trim).collect())
+    }
+    let fields = split(&sort.fields);
+    let orders = split(&sort.orders);
+    let invalid = |reason: &str| Error::InvalidSortError {
+        sort: format!("{:?} {:?}", sort.fields, sort.orders),
+        reason: reason.to_owned(),
+    };
+    if orders.len() > fields.len() {
+        return Err(invalid("more sort orders than sort fields"));
+    }
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let column = T::column_names()
+                .into_iter()
+                .find(|c| c.eq_ignore_ascii_case(field))
+                .ok_or_else(|| invalid("unknown column"))?;
+            let descending = match orders.get(i).map(|o| o.to_ascii_lowercase()).as_deref() {
+                None | Some("asc") => false,
+                Some("desc") => true,
+                Some(_) => return Err(invalid("sort order has to be asc or desc")),
+            };
+            Ok(SortColumn { column, descending })
+        })
+        .collect()
+}
+
+/// The sort columns followed by the key of `T`, so that the order of rows
+/// is the same for every page.
+pub fn order_columns<T: SchemaTable>(sort: &[SortColumn]) -> Vec<SortColumn> {
+    sort.iter()
+        .copied()
+        .chain(
+            T::key_attrs()
+                .into_iter()
+                .filter(|key| !sort.iter().any(|s| s.column == *key))
+                .map(|column| SortColumn {
+                    column,
+                    descending: false,
+                }),
+        )
+        .collect()
+}
+
+pub fn order_by(order: &[SortColumn]) -> String {
+    order
+        .iter()
+        .map(|s| format!("{} {}", s.column, if s.descending { "desc" } else { "asc" }))
+        .collect::<Vec<_>>()
+        .join(",")
+}
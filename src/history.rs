@@ -0,0 +1,257 @@
+//! Versions of table rows, rebuilt from the changes recorded in `audit_log`.
+//!
+//! The versions of a row follow its key back through updates that changed it. A table
+//! "as of" a time is the current table with the changes made since then undone, so rows
+//! that haven't changed since the audit log was introduced are part of it too. It's paged
+//! like the current table, and only the rows of the page are read from it.
+
+use crate::backend::{RowValue, SqlValue};
+use crate::database::DbConnection;
+use crate::database_operations::{
+    count_rows, get_audit_entries, get_row_audit_entries, json_key, load_data, SchemaTable,
+};
+use crate::error::Error;
+use crate::filter::{parse_value, Condition, Operator};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// A row as it was from `valid_from` until `valid_to`. `row` is `None` while the row
+/// was deleted, and versions from before the audit log have no `valid_from`.
+#[derive(Serialize, Debug)]
+pub struct Version {
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_to: Option<DateTime<Utc>>,
+    pub actor: Option<String>,
+    pub operation: Option<String>,
+    pub row: Option<Value>,
+}
+
+fn parse_row(value: &Option<String>) -> Result<Option<Value>, Error> {
+    Ok(value.as_deref().map(serde_json::from_str).transpose()?)
+}
+
+/// Column and value of the key given in a url, which has to be a single column.
+fn parse_key<T>(key: &str) -> Result<(&'static str, SqlValue), Error>
+where
+    T: SchemaTable,
+{
+    let missing = || Error::KeyDoesntExistError {
+        table_name: T::table_name().to_owned(),
+    };
+    let key_column = match T::key_attrs().as_slice() {
+        [column] => *column,
+        _ => return Err(missing()),
+    };
+    let column = T::columns()
+        .into_iter()
+        .find(|c| c.name == key_column)
+        .ok_or_else(missing)?;
+    let value = parse_value(column.column_type, key).ok_or_else(missing)?;
+    Ok((column.name, value))
+}
+
+fn json_value(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Int(v) => Value::from(v),
+        SqlValue::Float(v) => Value::from(v),
+        SqlValue::Text(v) => Value::from(v),
+        SqlValue::DateTime(v) => Value::from(v.to_rfc3339()),
+    }
+}
+
+/// Versions of the row with the key, oldest first.
+pub fn row_history<T>(connection: &DbConnection, key: &str) -> Result<Vec<Version>, Error>
+where
+    T: SchemaTable + RowValue + Serialize + Debug,
+{
+    let (column, value) = parse_key::<T>(key)?;
+    let condition = Condition {
+        column,
+        operator: Operator::Equal,
+        value: value.clone(),
+    };
+    let current = load_data::<T>(connection, 1, 1, &[condition], &[])?
+        .into_iter()
+        .next()
+        .map(serde_json::to_value)
+        .transpose()?;
+    // deleted rows are only found by their key in the audit log
+    let mut wanted = json_key::<T>(&json!({ column: json_value(value) }).0);
+
+    let mut changes = Vec::new();
+    let mut before = None;
+    'keys: loop {
+        let entries = get_row_audit_entries::<T>(connection, &wanted, before.as_ref())?;
+        for entry in entries.into_iter().rev() {
+            let new_key = parse_row(&entry.new_value)?.map(|row| json_key::<T>(&row));
+            let touches = match entry.operation.as_str() {
+                "delete" => entry.row_key == wanted,
+                _ => new_key.as_ref() == Some(&wanted),
+            };
+            if !touches {
+                continue;
+            }
+            // an update may have changed the key, older changes have the old one
+            if entry.operation == "update" && entry.row_key != wanted {
+                wanted = entry.row_key.clone();
+                before = Some(entry.clone());
+                changes.push(entry);
+                continue 'keys;
+            }
+            changes.push(entry);
+        }
+        break;
+    }
+    changes.reverse();
+
+    if changes.is_empty() {
+        return match current {
+            Some(row) => Ok(vec![Version {
+                valid_from: None,
+                valid_to: None,
+                actor: None,
+                operation: None,
+                row: Some(row),
+            }]),
+            None => Err(Error::KeyDoesntExistError {
+                table_name: T::table_name().to_owned(),
+            }),
+        };
+    }
+    let mut versions = Vec::new();
+    if changes[0].operation != "insert" {
+        versions.push(Version {
+            valid_from: None,
+            valid_to: None,
+            actor: None,
+            operation: None,
+            row: parse_row(&changes[0].old_value)?,
+        });
+    }
+    for change in changes {
+        versions.push(Version {
+            valid_from: Some(change.changed_at),
+            valid_to: None,
+            actor: Some(change.actor.clone()),
+            operation: Some(change.operation.clone()),
+            row: parse_row(&change.new_value)?,
+        });
+    }
+    for i in 1..versions.len() {
+        versions[i - 1].valid_to = versions[i].valid_from;
+    }
+    Ok(versions)
+}
+
+/// Conditions selecting the row with the key, a `row_key` of `audit_log`.
+fn key_conditions<T>(key: &str) -> Result<Vec<Condition>, Error>
+where
+    T: SchemaTable,
+{
+    let key = serde_json::from_str::<Value>(key)?;
+    T::columns()
+        .into_iter()
+        .filter(|column| T::key_attrs().contains(&column.name))
+        .map(|column| {
+            let value = match &key[column.name] {
+                Value::String(value) => parse_value(column.column_type, value),
+                Value::Null => None,
+                value => parse_value(column.column_type, &value.to_string()),
+            }
+            .ok_or_else(|| Error::KeyDoesntExistError {
+                table_name: T::table_name().to_owned(),
+            })?;
+            Ok(Condition {
+                column: column.name,
+                operator: Operator::Equal,
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Orders rows given as JSON by their key columns, like `order by` does with numbers and
+/// with text in the binary collation.
+fn key_order<T>(a: &Value, b: &Value) -> Ordering
+where
+    T: SchemaTable,
+{
+    T::key_attrs()
+        .into_iter()
+        .map(|column| match (&a[column], &b[column]) {
+            (Value::Number(a), Value::Number(b)) => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        })
+        .find(|order| *order != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The rows from `record_start` to `record_end` of the table as it was at the time,
+/// ordered by key, and the number of its rows then. Only the changes made since then
+/// are read from the audit log.
+pub fn table_as_of<T>(
+    connection: &DbConnection,
+    time: DateTime<Utc>,
+    record_start: usize,
+    record_end: usize,
+) -> Result<(usize, Vec<Value>), Error>
+where
+    T: SchemaTable + RowValue + Serialize + Debug,
+{
+    // rows changed since then as they were at the time, `None` if they didn't exist
+    let mut changed = BTreeMap::new();
+    let undone = get_audit_entries(connection, T::table_name(), Some(time))?;
+    for entry in undone.iter().rev() {
+        if let Some(new) = parse_row(&entry.new_value)? {
+            changed.insert(json_key::<T>(&new), None);
+        }
+        if let Some(old) = parse_row(&entry.old_value)? {
+            changed.insert(entry.row_key.clone(), Some(old));
+        }
+    }
+    let mut changed_now = 0;
+    for key in changed.keys() {
+        changed_now += count_rows::<T>(connection, &key_conditions::<T>(key)?)?;
+    }
+    let mut restored = changed
+        .values()
+        .filter_map(Clone::clone)
+        .collect::<Vec<_>>();
+    restored.sort_by(key_order::<T>);
+    let count = count_rows::<T>(connection, &[])? - changed_now + restored.len();
+
+    // the current rows that are left after the changed ones fill the page
+    let mut unchanged = Vec::new();
+    for row in load_data::<T>(connection, 1, record_end + changed_now, &[], &[])? {
+        let row = serde_json::to_value(row)?;
+        if !changed.contains_key(&json_key::<T>(&row)) {
+            unchanged.push(row);
+        }
+    }
+    let mut unchanged = unchanged.into_iter().peekable();
+    let mut restored = restored.into_iter().peekable();
+    let mut rows = Vec::new();
+    while rows.len() < record_end {
+        let next = match (unchanged.peek(), restored.peek()) {
+            (Some(current), Some(old)) if key_order::<T>(old, current) == Ordering::Less => {
+                restored.next()
+            }
+            (Some(_), _) => unchanged.next(),
+            (None, _) => restored.next(),
+        };
+        match next {
+            Some(row) => rows.push(row),
+            None => break,
+        }
+    }
+    Ok((count, rows.into_iter().skip(record_start - 1).collect()))
+}
@@ -0,0 +1,188 @@
+//! Throttling of failed logins.
+//!
+//! Failures are counted per username and per client address in `login_failures`. Every
+//! failure doubles the wait before the next attempt is accepted, starting at one second,
+//! and `login_max_failures` of them lock the login for `login_lockout_minutes`. An address
+//! may fail four times as often, since several users can share it. Counts are forgotten
+//! after a lockout period without failures.
+
+use crate::database::DbConnection;
+use crate::database_operations::{
+    add_login_failure, delete_data, get_data, insert_data, transaction, update_data,
+};
+use crate::error::Error;
+use crate::schema::LoginFailure;
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+use rocket::config::ConfigError;
+use rocket::fairing::AdHoc;
+
+const ADDRESS_FAILURES_FACTOR: i64 = 4;
+
+/// Login settings from `Rocket.toml`, managed by `config_fairing`.
+#[derive(Debug, Clone, Copy)]
+pub struct LoginConfig {
+    pub max_failures: i64,
+    pub lockout: Duration,
+    /// Whether admins have to use two-factor authentication.
+    pub require_admin_2fa: bool,
+}
+
+/// Reads the login settings, by default 5 failures lock the login for 15 minutes
+/// and two-factor authentication is optional.
+pub fn config_fairing() -> AdHoc {
+    AdHoc::on_attach("Login settings", |rocket| {
+        let positive = |name: &str, default: i64| match rocket.config().get_int(name) {
+            Ok(value) if value > 0 => Ok(value),
+            Ok(_) => Err(format!("{} has to be positive", name)),
+            Err(ConfigError::Missing(_)) => Ok(default),
+            Err(e) => Err(e.to_string()),
+        };
+        let config = positive("login_max_failures", 5).and_then(|max_failures| {
+            Ok(LoginConfig {
+                max_failures,
+                lockout: Duration::minutes(positive("login_lockout_minutes", 15)?),
+                require_admin_2fa: match rocket.config().get_bool("require_admin_2fa") {
+                    Ok(value) => value,
+                    Err(ConfigError::Missing(_)) => false,
+                    Err(e) => return Err(e.to_string()),
+                },
+            })
+        });
+        match config {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                error!("Invalid login configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+fn user_subject(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+fn address_subject(address: &str) -> String {
+    format!("ip:{}", address)
+}
+
+fn find(connection: &DbConnection, subject: String) -> Result<Option<LoginFailure>, Error> {
+    get_data(
+        connection,
+        &LoginFailure {
+            subject,
+            failures: 0,
+            last_failure: Utc::now(),
+            locked_until: Utc::now(),
+        },
+    )
+}
+
+/// Subjects of a login attempt with the number of failures that locks them.
+fn subjects(config: &LoginConfig, username: &str, address: Option<&str>) -> Vec<(String, i64)> {
+    let mut subjects = vec![(user_subject(username), config.max_failures)];
+    if let Some(address) = address {
+        subjects.push((
+            address_subject(address),
+            config.max_failures * ADDRESS_FAILURES_FACTOR,
+        ));
+    }
+    subjects
+}
+
+/// When the next login attempt for the username from the address is accepted,
+/// if it can't be made right away.
+pub fn blocked_until(
+    connection: &DbConnection,
+    username: &str,
+    address: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let now = Utc::now();
+    let mut until = None;
+    for subject in std::iter::once(user_subject(username)).chain(address.map(address_subject)) {
+        if let Some(failure) = find(connection, subject)? {
+            if failure.locked_until > now {
+                until = until.max(Some(failure.locked_until));
+            }
+        }
+    }
+    Ok(until)
+}
+
+/// Counts a failed login for the username and the address and returns the number
+/// of failures of the username. Runs in a transaction of its own, which is tried again
+/// if a concurrent failure inserted the first row of a subject meanwhile.
+pub fn record_failure(
+    connection: &DbConnection,
+    config: &LoginConfig,
+    username: &str,
+    address: Option<&str>,
+) -> Result<i64, Error> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match transaction(connection, || {
+            count_failure(connection, config, username, address)
+        }) {
+            Err(e) if e.is_unique_violation() && attempts < 3 => continue,
+            result => return result,
+        }
+    }
+}
+
+fn count_failure(
+    connection: &DbConnection,
+    config: &LoginConfig,
+    username: &str,
+    address: Option<&str>,
+) -> Result<i64, Error> {
+    let now = Utc::now();
+    let mut counts = Vec::new();
+    for (subject, max_failures) in subjects(config, username, address) {
+        let new = LoginFailure {
+            subject,
+            failures: 1,
+            last_failure: now,
+            locked_until: now,
+        };
+        // the count is raised first, the row stays locked while the wait is set
+        let old = add_login_failure(connection, &new)?;
+        let failures = match &old {
+            Some(old) if old.last_failure + config.lockout > now => old.failures,
+            _ => 1,
+        };
+        let wait = if i64::from(failures) >= max_failures {
+            config.lockout
+        } else {
+            Duration::seconds(1 << (failures - 1).min(30)).min(config.lockout)
+        };
+        let new = LoginFailure {
+            failures,
+            locked_until: now + wait,
+            ..new
+        };
+        match old {
+            Some(old) => update_data(connection, None, &old, &new)?,
+            None => insert_data(connection, None, &new)?,
+        };
+        counts.push(i64::from(failures));
+    }
+    Ok(counts[0])
+}
+
+/// Forgets the failures of the username, after a successful login or an admin unlock.
+pub fn reset(connection: &DbConnection, username: &str) -> Result<(), Error> {
+    match find(connection, user_subject(username))? {
+        Some(failure) => delete_data(connection, None, &failure),
+        None => Ok(()),
+    }
+}
+
+/// When the login of the username is unlocked, if it's locked now.
+pub fn locked_until(
+    connection: &DbConnection,
+    username: &str,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    blocked_until(connection, username, None)
+}
@@ -8,122 +8,486 @@ extern crate lazy_static;
 #[macro_use]
 extern crate smart_default;
 
+mod audit;
 mod auth;
+mod backend;
+mod batch;
 mod core;
+mod csrf;
+mod cursor;
+mod database;
 mod database_operations;
 mod database_oracle;
+mod database_postgres;
+mod database_sqlite;
+mod ddl;
 mod error;
+mod filter;
+mod history;
+mod lockout;
+mod migrations;
+mod password;
 mod read_insert_update_delete;
+mod roles;
 mod schema;
+mod sessions;
+mod table_registry;
+#[cfg(test)]
+mod test_database;
+mod tokens;
+mod trash;
+mod two_factor;
+mod users;
 
-use crate::auth::{User, UserFullData};
-use crate::database_operations::{get_user, update_data};
-use crate::database_oracle::OracleConnection;
+use crate::audit::AUDIT_ROUTES;
+use crate::auth::{
+    Admin, Delete, Insert, Operation, PasswordExpired, TwoFactorForm, Update, User, UserFullData,
+};
+use crate::batch::BATCH_ROUTES;
+use crate::csrf::CsrfToken;
+use crate::database::OracleConnection;
+use crate::database_operations::{
+    delete_data, get_granted_operations, get_user, is_admin, update_data,
+};
 use crate::error::Error;
+use crate::lockout::LoginConfig;
+use crate::password::PasswordPolicy;
 use crate::read_insert_update_delete::CRUD_ROUTES;
+use crate::roles::ROLE_ROUTES;
 use crate::schema::UserInfo;
+use crate::sessions::{ClientInfo, SESSION_COOKIE, SESSION_ROUTES};
+use crate::tokens::TOKEN_ROUTES;
+use crate::trash::TRASH_ROUTES;
+use crate::two_factor::TWO_FACTOR_ROUTES;
+use crate::users::USER_ROUTES;
 use chrono::{self, Utc};
-use log::info;
+use log::{error, info, warn};
 use rocket::http::{Cookie, Cookies};
-use rocket::request::Form;
-use rocket::response::Redirect;
-use rocket::{Request, Response};
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{Flash, Redirect};
+use rocket::{Request, Response, State};
 use rocket_contrib::serve::StaticFiles;
 use rocket_contrib::templates::tera::Value;
 use rocket_contrib::templates::Template;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-fn create_context(templ_name: &str, is_admin: bool) -> HashMap<&str, Value> {
+fn create_context<'a>(
+    templ_name: &'a str,
+    is_admin: bool,
+    csrf: &CsrfToken,
+) -> HashMap<&'a str, Value> {
     let mut map = HashMap::new();
     map.insert("current_tname", Value::from(templ_name));
     map.insert("is_admin", Value::from(is_admin));
+    map.insert("csrf_token", Value::from(csrf.0.as_str()));
     map
 }
 
+/// Context of a table page, with the operations the user may do on the table
+/// so that the page only shows the controls for them.
+fn create_table_context<'a>(
+    templ_name: &'a str,
+    connection: &OracleConnection,
+    user: &User,
+    csrf: &CsrfToken,
+) -> Result<HashMap<&'a str, Value>, Error> {
+    let operations = get_granted_operations(connection, &user.data.username, templ_name)?;
+    let can = |operation: &str| Value::from(operations.iter().any(|o| o == operation));
+    let mut map = create_context(templ_name, user.is_admin, csrf);
+    map.insert("can_insert", can(Insert::NAME));
+    map.insert("can_update", can(Update::NAME));
+    map.insert("can_delete", can(Delete::NAME));
+    Ok(map)
+}
+
 #[get("/")]
-fn index(user: User) -> Template {
-    Template::render("home", create_context("home", user.is_admin))
+fn index(user: User, csrf: CsrfToken) -> Template {
+    Template::render("home", create_context("home", user.is_admin, &csrf))
 }
 
 #[get("/continents.tera")]
-fn continents(user: User) -> Template {
-    Template::render("continents", create_context("continents", user.is_admin))
+fn continents(
+    connection: OracleConnection,
+    user: User,
+    csrf: CsrfToken,
+) -> Result<Template, Error> {
+    Ok(Template::render(
+        "continents",
+        create_table_context("continents", &connection, &user, &csrf)?,
+    ))
 }
 
 #[get("/cities.tera")]
-fn cities(user: User) -> Template {
-    Template::render("cities", create_context("cities", user.is_admin))
+fn cities(connection: OracleConnection, user: User, csrf: CsrfToken) -> Result<Template, Error> {
+    Ok(Template::render(
+        "cities",
+        create_table_context("cities", &connection, &user, &csrf)?,
+    ))
 }
 
 #[get("/countries.tera")]
-fn countries(user: User) -> Template {
-    Template::render("countries", create_context("countries", user.is_admin))
+fn countries(connection: OracleConnection, user: User, csrf: CsrfToken) -> Result<Template, Error> {
+    Ok(Template::render(
+        "countries",
+        create_table_context("countries", &connection, &user, &csrf)?,
+    ))
 }
 
 #[get("/districts.tera")]
-fn districts(user: User) -> Template {
-    Template::render("districts", create_context("districts", user.is_admin))
+fn districts(connection: OracleConnection, user: User, csrf: CsrfToken) -> Result<Template, Error> {
+    Ok(Template::render(
+        "districts",
+        create_table_context("districts", &connection, &user, &csrf)?,
+    ))
 }
 
 #[get("/regions.tera")]
-fn regions(user: User) -> Template {
-    Template::render("regions", create_context("regions", user.is_admin))
+fn regions(connection: OracleConnection, user: User, csrf: CsrfToken) -> Result<Template, Error> {
+    Ok(Template::render(
+        "regions",
+        create_table_context("regions", &connection, &user, &csrf)?,
+    ))
+}
+
+#[get("/users.tera")]
+fn users(_admin: Admin, csrf: CsrfToken) -> Template {
+    Template::render("users", create_context("users", true, &csrf))
+}
+
+#[get("/audit_log.tera")]
+fn audit_log(_admin: Admin, csrf: CsrfToken) -> Template {
+    Template::render("audit_log", create_context("audit_log", true, &csrf))
+}
+
+#[get("/trash.tera")]
+fn trash_page(_admin: Admin, csrf: CsrfToken) -> Template {
+    Template::render("trash", create_context("trash", true, &csrf))
+}
+
+#[get("/sessions.tera")]
+fn user_sessions(user: User, csrf: CsrfToken) -> Template {
+    Template::render("sessions", create_context("sessions", user.is_admin, &csrf))
+}
+
+#[get("/security.tera")]
+fn security(user: User, csrf: CsrfToken) -> Template {
+    Template::render("security", create_context("security", user.is_admin, &csrf))
+}
+
+#[get("/tokens.tera")]
+fn api_tokens(user: User, csrf: CsrfToken) -> Template {
+    Template::render("tokens", create_context("tokens", user.is_admin, &csrf))
+}
+
+#[get("/password.tera")]
+fn password_page(user: User, policy: State<PasswordPolicy>, csrf: CsrfToken) -> Template {
+    let mut context = create_context("password", user.is_admin, &csrf);
+    context.insert("password_expired", Value::from(user.password_expired));
+    context.insert("min_length", Value::from(policy.min_length));
+    Template::render("password", context)
 }
 
 #[get("/login.tera")]
-fn login() -> Template {
-    Template::render("login", create_context("login", false))
+fn login(flash: Option<FlashMessage>, csrf: CsrfToken) -> Template {
+    let mut context = create_context("login", false, &csrf);
+    if let Some(flash) = flash {
+        context.insert("error", Value::from(flash.msg()));
+    }
+    Template::render("login", context)
+}
+
+fn login_failed(message: &str) -> Flash<Redirect> {
+    Flash::error(Redirect::to("/login.tera"), message)
+}
+
+/// Where a step of the login leads.
+#[derive(Responder)]
+enum LoginStep {
+    Next(Redirect),
+    /// Recovery codes of a new two-factor enrollment, only shown once.
+    RecoveryCodes(Template),
+    Failed(Flash<Redirect>),
+}
+
+/// Starts a session for the user, who passed every login step.
+fn finish_login(
+    connection: &OracleConnection,
+    cookies: &mut Cookies,
+    client: ClientInfo,
+    user: &UserInfo,
+) -> Result<(), Error> {
+    lockout::reset(connection, &user.username)?;
+    two_factor::end_pending_login(cookies);
+    let session_id = sessions::start(connection, &user.username, client)?;
+    cookies.add_private(Cookie::new(SESSION_COOKIE, session_id));
+    update_data(
+        connection,
+        None,
+        user,
+        &UserInfo {
+            last_appearance: Utc::now(),
+            ..user.clone()
+        },
+    )?;
+    info!(
+        "{}",
+        format!(
+            "Login attempt with username {:?} successful",
+            &user.username
+        )
+    );
+    Ok(())
 }
 
 #[post("/login.tera", data = "<user>")]
 fn auth_user(
     connection: OracleConnection,
     mut cookies: Cookies,
+    client: ClientInfo,
+    login_config: State<LoginConfig>,
     user: Form<UserFullData>,
-) -> Result<Redirect, Error> {
-    dbg!(&user);
+) -> Result<LoginStep, Error> {
     info!(
         "{}",
-        format!("Login attempt with username {:?}", &user.0.username)
+        format!(
+            "Login attempt with username {:?} from {:?}",
+            &user.0.username, client.ip_address
+        )
     );
+    if !csrf::is_valid(&mut cookies, &user.csrf_token) {
+        warn!(
+            "{}",
+            format!(
+                "Login attempt with username {:?} without a valid CSRF token",
+                &user.username
+            )
+        );
+        return Ok(LoginStep::Failed(login_failed(
+            "The login form has expired, please try again",
+        )));
+    }
+    let address = client.ip_address.clone();
+    if let Some(until) = lockout::blocked_until(&*connection, &user.username, address.as_deref())? {
+        warn!(
+            "{}",
+            format!(
+                "Login attempt with username {:?} from {:?} blocked until {}",
+                &user.username, address, until
+            )
+        );
+        return Ok(LoginStep::Failed(login_failed(&format!(
+            "Too many failed attempts, try again after {}",
+            until.format("%H:%M:%S UTC")
+        ))));
+    }
     let pass = user.password.clone();
-    let user = get_user(
+    let stored = get_user(
         &*connection,
         &UserInfo {
             username: user.username.clone(),
             ..UserInfo::default()
         },
     )?;
-    if let Some(u) = user {
-        if pass == u.password {
-            cookies.add_private(Cookie::new("user_name", u.username.clone()));
-            update_data(
+    let u = match stored {
+        Some(u) if u.is_disabled != "y" && password::verify(&pass, &u.password)? => u,
+        _ => {
+            // the client learns nothing more if the failure can't be counted
+            match lockout::record_failure(
                 &*connection,
-                &u,
-                &UserInfo {
-                    last_appearance: Utc::now(),
-                    ..u.clone()
-                },
-            )?;
-            info!(
-                "{}",
-                format!("Login attempt with username {:?} successful", &u.username)
-            );
+                &login_config,
+                &user.username,
+                address.as_deref(),
+            ) {
+                Ok(failures) => warn!(
+                    "{}",
+                    format!(
+                        "Login attempt with username {:?} from {:?} failed, {} failures in a row",
+                        &user.username, address, failures
+                    )
+                ),
+                Err(e) => error!(
+                    "{}",
+                    format!(
+                        "Failed login attempt with username {:?} from {:?} wasn't counted: {}",
+                        &user.username, address, e
+                    )
+                ),
+            }
+            return Ok(LoginStep::Failed(login_failed(
+                "Invalid username or password",
+            )));
         }
     };
-    Ok(Redirect::to("/"))
+    // rows with plain text passwords are upgraded on their first login
+    let u = if password::needs_rehash(&u.password) {
+        update_data(
+            &*connection,
+            None,
+            &u,
+            &UserInfo {
+                password: password::hash(&pass)?,
+                ..u.clone()
+            },
+        )?
+    } else {
+        u
+    };
+    let two_factor_enabled = two_factor::is_enabled(&*connection, &u.username)?;
+    if two_factor_enabled
+        || (login_config.require_admin_2fa && is_admin(&*connection, &u.username)?)
+    {
+        // admins who have to use two-factor authentication enroll now, and keep
+        // the secret they may have scanned on an earlier login
+        if !two_factor_enabled
+            && two_factor::pending_enrollment(&*connection, &u.username)?.is_none()
+        {
+            two_factor::start_enrollment(&*connection, &u.username)?;
+        }
+        two_factor::start_pending_login(&mut cookies, &u.username);
+        return Ok(LoginStep::Next(Redirect::to("/two_factor.tera")));
+    }
+    finish_login(&connection, &mut cookies, client, &u)?;
+    Ok(LoginStep::Next(Redirect::to("/")))
+}
+
+/// The user who gave the right password for the second login step, if it's still valid.
+fn pending_user(
+    connection: &OracleConnection,
+    cookies: &mut Cookies,
+) -> Result<Option<UserInfo>, Error> {
+    let username = match two_factor::pending_login(cookies) {
+        Some(username) => username,
+        None => return Ok(None),
+    };
+    Ok(get_user(
+        connection,
+        &UserInfo {
+            username,
+            ..UserInfo::default()
+        },
+    )?
+    .filter(|u| u.is_disabled != "y"))
+}
+
+#[get("/two_factor.tera")]
+fn two_factor_login(
+    connection: OracleConnection,
+    flash: Option<FlashMessage>,
+    csrf: CsrfToken,
+    mut cookies: Cookies,
+) -> Result<Result<Template, Redirect>, Error> {
+    let user = match pending_user(&connection, &mut cookies)? {
+        Some(user) => user,
+        None => return Ok(Err(Redirect::to("/login.tera"))),
+    };
+    let mut context = create_context("two_factor", false, &csrf);
+    context.insert("username", Value::from(user.username.as_str()));
+    // the enrollment of admins who have to use two-factor authentication, see `auth_user`
+    if !two_factor::is_enabled(&*connection, &user.username)? {
+        match two_factor::pending_enrollment(&*connection, &user.username)? {
+            Some(enrollment) => {
+                context.insert("secret", Value::from(enrollment.secret));
+                context.insert("qr_svg", Value::from(enrollment.qr_svg));
+            }
+            None => return Ok(Err(Redirect::to("/login.tera"))),
+        }
+    }
+    if let Some(flash) = flash {
+        context.insert("error", Value::from(flash.msg()));
+    }
+    Ok(Ok(Template::render("two_factor", context)))
+}
+
+#[post("/two_factor.tera", data = "<form>")]
+fn two_factor_auth(
+    connection: OracleConnection,
+    client: ClientInfo,
+    login_config: State<LoginConfig>,
+    mut cookies: Cookies,
+    form: Form<TwoFactorForm>,
+) -> Result<LoginStep, Error> {
+    let failed =
+        |message: &str| LoginStep::Failed(Flash::error(Redirect::to("/two_factor.tera"), message));
+    if !csrf::is_valid(&mut cookies, &form.csrf_token) {
+        return Ok(failed("The form has expired, please try again"));
+    }
+    let user = match pending_user(&connection, &mut cookies)? {
+        Some(user) => user,
+        None => {
+            return Ok(LoginStep::Failed(login_failed(
+                "The login has expired, please sign in again",
+            )))
+        }
+    };
+    let address = client.ip_address.clone();
+    if let Some(until) = lockout::blocked_until(&*connection, &user.username, address.as_deref())? {
+        return Ok(failed(&format!(
+            "Too many failed attempts, try again after {}",
+            until.format("%H:%M:%S UTC")
+        )));
+    }
+    let (accepted, recovery_codes) = if two_factor::is_enabled(&*connection, &user.username)? {
+        (
+            two_factor::verify(&*connection, &user.username, &form.code)?,
+            None,
+        )
+    } else {
+        let codes = two_factor::confirm_enrollment(&*connection, &user.username, &form.code)?;
+        (codes.is_some(), codes)
+    };
+    if !accepted {
+        match lockout::record_failure(
+            &*connection,
+            &login_config,
+            &user.username,
+            address.as_deref(),
+        ) {
+            Ok(failures) => warn!(
+                "{}",
+                format!(
+                    "Two-factor code of {:?} from {:?} was wrong, {} failures in a row",
+                    &user.username, address, failures
+                )
+            ),
+            Err(e) => error!(
+                "{}",
+                format!(
+                    "Wrong two-factor code of {:?} from {:?} wasn't counted: {}",
+                    &user.username, address, e
+                )
+            ),
+        }
+        return Ok(failed("The code is wrong"));
+    }
+    finish_login(&connection, &mut cookies, client, &user)?;
+    Ok(match recovery_codes {
+        Some(codes) => LoginStep::RecoveryCodes(Template::render(
+            "recovery_codes",
+            json!({ "recovery_codes": codes }),
+        )),
+        None => LoginStep::Next(Redirect::to("/")),
+    })
 }
 
 #[get("/signout.tera")]
-fn signout_user(mut cookies: Cookies) -> Redirect {
-    cookies.remove_private(Cookie::named("user_name"));
-    Redirect::to("/")
+fn signout_user(connection: OracleConnection, mut cookies: Cookies) -> Result<Redirect, Error> {
+    if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+        if let Some(session) = sessions::find(&*connection, cookie.value())? {
+            delete_data(&*connection, None, &session)?;
+        }
+        cookies.remove_private(cookie);
+    }
+    Ok(Redirect::to("/"))
 }
 
 #[catch(401)]
 fn unauthorized(req: &Request) -> Redirect {
-    Redirect::to("/login.tera")
+    if req.local_cache(|| PasswordExpired(false)).0 {
+        Redirect::to("/password.tera")
+    } else {
+        Redirect::to("/login.tera")
+    }
 }
 
 fn main() -> Result<(), fern::InitError> {
@@ -144,6 +508,20 @@ fn main() -> Result<(), fern::InitError> {
 
     dbg!(std::env::var("LD_LIBRARY_PATH"));
 
+    let args = std::env::args().collect::<Vec<_>>();
+    let command_result = match args.get(1).map(String::as_str) {
+        Some("migrate") => Some(migrations::command(&args[2..])),
+        Some("schema") => Some(ddl::command(&args[2..])),
+        _ => None,
+    };
+    if let Some(result) = command_result {
+        if let Err(e) = result {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let mut root_routes = routes![
         index,
         continents,
@@ -151,13 +529,35 @@ fn main() -> Result<(), fern::InitError> {
         countries,
         districts,
         regions,
+        users,
+        audit_log,
+        trash_page,
+        user_sessions,
+        security,
+        api_tokens,
+        password_page,
         login,
         auth_user,
+        two_factor_login,
+        two_factor_auth,
         signout_user
     ];
     root_routes.extend(CRUD_ROUTES.clone());
+    root_routes.extend(BATCH_ROUTES.clone());
+    root_routes.extend(USER_ROUTES.clone());
+    root_routes.extend(ROLE_ROUTES.clone());
+    root_routes.extend(AUDIT_ROUTES.clone());
+    root_routes.extend(TRASH_ROUTES.clone());
+    root_routes.extend(TOKEN_ROUTES.clone());
+    root_routes.extend(SESSION_ROUTES.clone());
+    root_routes.extend(TWO_FACTOR_ROUTES.clone());
     rocket::ignite()
         .attach(OracleConnection::fairing())
+        .attach(migrations::check_fairing())
+        .attach(sessions::config_fairing())
+        .attach(lockout::config_fairing())
+        .attach(password::config_fairing())
+        .attach(cursor::config_fairing())
         .attach(Template::fairing())
         .mount("/", root_routes)
         .mount("/images", StaticFiles::from("./images"))
@@ -0,0 +1,330 @@
+//! Versioned schema migrations.
+//!
+//! Every backend has its own numbered `up`/`down` scripts in `migrations/<dialect>`,
+//! which are embedded into the binary. Applied versions are recorded in the
+//! `schema_migrations` table. Oracle commits DDL on its own, so a migration that fails
+//! halfway there has to be cleaned up by hand before it's applied again.
+
+use crate::backend::{Backend, Dialect, SqlValue};
+use crate::database::{DbConnection, OracleConnection};
+use crate::error::Error;
+use chrono::Utc;
+use log::{error, info};
+use rocket::fairing::AdHoc;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! migrations {
+    ($dialect:literal: $($version:literal => $name:literal),* $(,)?) => {
+        &[$(Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $dialect, "/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $dialect, "/", $name, ".down.sql")),
+        }),*]
+    };
+}
+
+const ORACLE_MIGRATIONS: &[Migration] = migrations!("oracle":
+    1 => "0001_create_tables",
+    2 => "0002_add_user_disabled",
+    3 => "0003_create_roles",
+    4 => "0004_create_api_tokens",
+    5 => "0005_create_sessions",
+    6 => "0006_create_login_failures",
+    7 => "0007_create_two_factor",
+    8 => "0008_add_password_policy",
+    9 => "0009_create_audit_log",
+    10 => "0010_create_trash",
+);
+
+const SQLITE_MIGRATIONS: &[Migration] = migrations!("sqlite":
+    1 => "0001_create_tables",
+    2 => "0002_add_user_disabled",
+    3 => "0003_create_roles",
+    4 => "0004_create_api_tokens",
+    5 => "0005_create_sessions",
+    6 => "0006_create_login_failures",
+    7 => "0007_create_two_factor",
+    8 => "0008_add_password_policy",
+    9 => "0009_create_audit_log",
+    10 => "0010_create_trash",
+);
+
+const POSTGRES_MIGRATIONS: &[Migration] = migrations!("postgres":
+    1 => "0001_create_tables",
+    2 => "0002_add_user_disabled",
+    3 => "0003_create_roles",
+    4 => "0004_create_api_tokens",
+    5 => "0005_create_sessions",
+    6 => "0006_create_login_failures",
+    7 => "0007_create_two_factor",
+    8 => "0008_add_password_policy",
+    9 => "0009_create_audit_log",
+    10 => "0010_create_trash",
+);
+
+#[cfg(test)]
+impl Migration {
+    /// Statements of the `up` script, for comparing them with the generated schema.
+    pub fn up_statements(&self) -> Vec<String> {
+        statements(self.up)
+    }
+}
+
+/// Migrations for the dialect, ordered by version.
+pub fn migrations(dialect: Dialect) -> &'static [Migration] {
+    match dialect {
+        Dialect::Oracle => ORACLE_MIGRATIONS,
+        Dialect::Sqlite => SQLITE_MIGRATIONS,
+        Dialect::Postgres => POSTGRES_MIGRATIONS,
+    }
+}
+
+/// Splits a script into statements ending with `;`, leaving out `--` comment lines.
+fn statements(script: &str) -> Vec<String> {
+    script
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn tracking_table_exists(backend: &dyn Backend) -> Result<bool, Error> {
+    let sql = match backend.dialect() {
+        Dialect::Oracle => {
+            "select count(*) from user_tables where table_name = 'SCHEMA_MIGRATIONS'"
+        }
+        Dialect::Sqlite => {
+            "select count(*) from sqlite_master where type = 'table' and name = 'schema_migrations'"
+        }
+        Dialect::Postgres => {
+            "select count(*) from information_schema.tables \
+             where table_schema = current_schema() and table_name = 'schema_migrations'"
+        }
+    };
+    let count: i64 = backend.query(sql, &[])?[0].get_at(0)?;
+    Ok(count > 0)
+}
+
+fn create_tracking_table(backend: &dyn Backend) -> Result<(), Error> {
+    if tracking_table_exists(backend)? {
+        return Ok(());
+    }
+    let sql = match backend.dialect() {
+        Dialect::Oracle => {
+            "create table schema_migrations (version number(10) primary key, \
+             name varchar2(200) not null, applied_at timestamp not null)"
+        }
+        Dialect::Sqlite => {
+            "create table schema_migrations (version integer primary key, \
+             name text not null, applied_at text not null)"
+        }
+        Dialect::Postgres => {
+            "create table schema_migrations (version bigint primary key, \
+             name varchar(200) not null, applied_at timestamptz not null)"
+        }
+    };
+    backend.execute(sql, &[])?;
+    backend.commit()
+}
+
+/// Versions recorded in `schema_migrations`, in ascending order.
+fn applied_versions(backend: &dyn Backend) -> Result<Vec<i64>, Error> {
+    if !tracking_table_exists(backend)? {
+        return Ok(Vec::new());
+    }
+    backend
+        .query(
+            "select version from schema_migrations order by version",
+            &[],
+        )?
+        .iter()
+        .map(|row| row.get_at(0))
+        .collect()
+}
+
+/// Runs the script and records the change in the same transaction where the database allows it.
+fn run(
+    backend: &dyn Backend,
+    migration: &Migration,
+    script: &str,
+    record_sql: &str,
+    record_params: &[SqlValue],
+) -> Result<(), Error> {
+    let result = statements(script)
+        .iter()
+        .try_for_each(|statement| backend.execute(statement, &[]).map(|_| ()))
+        .and_then(|_| backend.execute(record_sql, record_params))
+        .and_then(|_| backend.commit());
+    result.or_else(|e| {
+        backend.rollback()?;
+        Err(Error::MigrationError {
+            migration: migration.name.to_owned(),
+            message: e.to_string(),
+        })
+    })
+}
+
+/// Applies all pending migrations in order and returns them.
+pub fn migrate_up(connection: &DbConnection) -> Result<Vec<&'static Migration>, Error> {
+    let backend = connection.backend();
+    create_tracking_table(backend)?;
+    let applied = applied_versions(backend)?;
+    let pending = migrations(backend.dialect())
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect::<Vec<_>>();
+    for migration in &pending {
+        let insert = format!(
+            "insert into schema_migrations (version, name, applied_at) values ({},{},{})",
+            backend.placeholder(1),
+            backend.placeholder(2),
+            backend.placeholder(3)
+        );
+        run(
+            backend,
+            migration,
+            migration.up,
+            &insert,
+            &[
+                migration.version.into(),
+                migration.name.into(),
+                Utc::now().into(),
+            ],
+        )?;
+        info!("Applied migration {}", migration.name);
+    }
+    Ok(pending)
+}
+
+/// Rolls back the last `steps` applied migrations, newest first, and returns them.
+pub fn migrate_down(
+    connection: &DbConnection,
+    steps: usize,
+) -> Result<Vec<&'static Migration>, Error> {
+    let backend = connection.backend();
+    let known = migrations(backend.dialect());
+    let mut rolled_back = Vec::new();
+    for version in applied_versions(backend)?.into_iter().rev().take(steps) {
+        let migration =
+            known
+                .iter()
+                .find(|m| m.version == version)
+                .ok_or_else(|| Error::MigrationError {
+                    migration: version.to_string(),
+                    message: "applied migration is unknown to this build".to_owned(),
+                })?;
+        let delete = format!(
+            "delete from schema_migrations where version = {}",
+            backend.placeholder(1)
+        );
+        run(
+            backend,
+            migration,
+            migration.down,
+            &delete,
+            &[migration.version.into()],
+        )?;
+        info!("Rolled back migration {}", migration.name);
+        rolled_back.push(migration);
+    }
+    Ok(rolled_back)
+}
+
+/// Every known migration with whether it has been applied.
+pub fn status(connection: &DbConnection) -> Result<Vec<(&'static Migration, bool)>, Error> {
+    let backend = connection.backend();
+    let applied = applied_versions(backend)?;
+    Ok(migrations(backend.dialect())
+        .iter()
+        .map(|m| (m, applied.contains(&m.version)))
+        .collect())
+}
+
+/// Refuses to launch while the database schema is behind the migrations of this build.
+pub fn check_fairing() -> AdHoc {
+    AdHoc::on_attach("Pending migrations check", |rocket| {
+        let pending = OracleConnection::get_one(&rocket)
+            .ok_or_else(|| Error::ConfigError {
+                message: "oracle_db database is not configured".to_owned(),
+            })
+            .and_then(|conn| status(&*conn));
+        match pending {
+            Ok(migrations) => {
+                let pending = migrations
+                    .iter()
+                    .filter(|(_, applied)| !applied)
+                    .map(|(m, _)| m.name)
+                    .collect::<Vec<_>>();
+                if pending.is_empty() {
+                    Ok(rocket)
+                } else {
+                    error!(
+                        "Pending migrations {:?}, run `migrate up` before starting the server",
+                        pending
+                    );
+                    Err(rocket)
+                }
+            }
+            Err(e) => {
+                error!("Couldn't check migrations: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Runs `migrate up`, `migrate down [steps]` or `migrate status` against `oracle_db`.
+pub fn command(args: &[String]) -> Result<(), Error> {
+    let rocket = rocket::ignite().attach(OracleConnection::fairing());
+    let conn = OracleConnection::get_one(&rocket).ok_or_else(|| Error::ConfigError {
+        message: "oracle_db database is not configured".to_owned(),
+    })?;
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["up"] => {
+            let applied = migrate_up(&*conn)?;
+            println!("{} migration(s) applied", applied.len());
+        }
+        ["down"] | ["down", _] => {
+            let steps = match args.get(1) {
+                Some(steps) => steps.parse().map_err(|_| Error::ConfigError {
+                    message: format!("invalid number of steps {:?}", steps),
+                })?,
+                None => 1,
+            };
+            let rolled_back = migrate_down(&*conn, steps)?;
+            println!("{} migration(s) rolled back", rolled_back.len());
+        }
+        ["status"] => {
+            for (migration, applied) in status(&*conn)? {
+                println!(
+                    "{} {}",
+                    if applied { "applied" } else { "pending" },
+                    migration.name
+                );
+            }
+        }
+        _ => {
+            return Err(Error::ConfigError {
+                message: "usage: migrate up | migrate down [steps] | migrate status".to_owned(),
+            })
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Password hashing for `users_info`.
+//!
+//! Passwords are stored as argon2id hashes in the PHC string format, which keeps the salt
+//! and the parameters next to the hash. Rows from before hashing was introduced still
+//! hold the plain password and are rehashed on the next successful login.
+//!
+//! New passwords have to follow the policy from `Rocket.toml`: at least
+//! `password_min_length` characters and none of the `password_history` previous ones.
+//! With `password_max_age_days` set, users whose password is older are sent to the
+//! password change page until they pick a new one.
+
+use crate::database::DbConnection;
+use crate::database_operations::{change_password, get_password_history};
+use crate::error::Error;
+use crate::schema::UserInfo;
+use argon2::{Config, Variant, Version};
+use chrono::{Duration, Utc};
+use log::error;
+use rand::Rng;
+use rocket::config::ConfigError;
+use rocket::fairing::AdHoc;
+
+fn config() -> Config<'static> {
+    Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: 19456,
+        time_cost: 2,
+        ..Config::default()
+    }
+}
+
+/// Hashes the password with a new random salt.
+pub fn hash(password: &str) -> Result<String, Error> {
+    let salt = rand::thread_rng().gen::<[u8; 16]>();
+    Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config())?)
+}
+
+/// Whether the stored password is still plain text instead of a hash.
+pub fn needs_rehash(stored: &str) -> bool {
+    !stored.starts_with("$argon2")
+}
+
+/// Checks the password against a stored hash, or a plain text password
+/// if the row hasn't been upgraded yet.
+pub fn verify(password: &str, stored: &str) -> Result<bool, Error> {
+    if needs_rehash(stored) {
+        Ok(password == stored)
+    } else {
+        Ok(argon2::verify_encoded(stored, password.as_bytes())?)
+    }
+}
+
+/// Password rules from `Rocket.toml`, managed by `config_fairing`.
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Number of previous passwords that can't be used again.
+    pub history: usize,
+    /// Age after which the password has to be changed, if any.
+    pub max_age: Option<Duration>,
+}
+
+impl PasswordPolicy {
+    pub fn has_expired(&self, user: &UserInfo) -> bool {
+        self.max_age
+            .iter()
+            .any(|age| user.password_changed_at + *age < Utc::now())
+    }
+}
+
+/// Reads the password rules, by default 8 characters, none of the last 5 passwords
+/// and no expiry.
+pub fn config_fairing() -> AdHoc {
+    AdHoc::on_attach("Password policy", |rocket| {
+        let count = |name: &str, default: i64| match rocket.config().get_int(name) {
+            Ok(value) if value >= 0 => Ok(value),
+            Ok(_) => Err(format!("{} can't be negative", name)),
+            Err(ConfigError::Missing(_)) => Ok(default),
+            Err(e) => Err(e.to_string()),
+        };
+        let policy = count("password_min_length", 8).and_then(|min_length| {
+            let max_age_days = count("password_max_age_days", 0)?;
+            Ok(PasswordPolicy {
+                min_length: min_length as usize,
+                history: count("password_history", 5)? as usize,
+                // 0 turns expiry off
+                max_age: Some(Duration::days(max_age_days)).filter(|_| max_age_days > 0),
+            })
+        });
+        match policy {
+            Ok(policy) => Ok(rocket.manage(policy)),
+            Err(e) => {
+                error!("Invalid password policy: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+fn rejected(message: String) -> Error {
+    Error::UserManagementError { message }
+}
+
+/// Checks that the new password follows the policy, comparing it with the current
+/// and the previous passwords of the user.
+pub fn check_policy(
+    connection: &DbConnection,
+    policy: &PasswordPolicy,
+    user: &UserInfo,
+    new: &str,
+) -> Result<(), Error> {
+    if new.chars().count() < policy.min_length {
+        return Err(rejected(format!(
+            "passwords need at least {} characters",
+            policy.min_length
+        )));
+    }
+    if !user.password.is_empty() && verify(new, &user.password)? {
+        return Err(rejected("the new password is the current one".to_owned()));
+    }
+    for previous in get_password_history(connection, &user.username)?
+        .iter()
+        .take(policy.history)
+    {
+        if verify(new, &previous.password)? {
+            return Err(rejected(format!(
+                "the password can't be one of the last {} passwords",
+                policy.history
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the new password against the policy and replaces the password of the user
+/// with it, returning the updated user.
+pub fn change(
+    connection: &DbConnection,
+    policy: &PasswordPolicy,
+    user: &UserInfo,
+    new: &str,
+) -> Result<UserInfo, Error> {
+    check_policy(connection, policy, user, new)?;
+    change_password(connection, user, &hash(new)?, policy.history)
+}
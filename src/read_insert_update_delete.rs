@@ -1,337 +1,187 @@
-use crate::auth::{Admin, User};
-use crate::core::OldNew;
-use crate::database_operations::{
-    count_rows, delete_data, insert_data, load_data, update_data, SchemaTable,
-};
-use crate::database_oracle::OracleConnection;
+use crate::auth::{Delete, Grant, Insert, Read, Update};
+use crate::csrf::Csrf;
+use crate::cursor::PageConfig;
+use crate::database::OracleConnection;
+use crate::database_operations::page_records;
 use crate::error::Error;
+use crate::filter::{FilterQuery, SortQuery};
 use crate::schema::{City, Continent, Country, District, Region};
+use crate::table_registry::TableRegistry;
+use chrono::{DateTime, Utc};
 use log::info;
-use r2d2_oracle::oracle::RowValue;
-use rocket::Route;
+use rocket::{Route, State};
 use rocket_contrib::json::{Json, JsonValue};
-use serde::Serialize;
-use std::fmt::Debug;
+use serde_json::Value;
 
 lazy_static! {
     pub static ref CRUD_ROUTES: Vec<Route> = routes![
         read_data,
-        continents_update,
-        continents_insert,
-        continents_delete,
-        cities_update,
-        cities_insert,
-        cities_delete,
-        countries_update,
-        countries_insert,
-        countries_delete,
-        districts_update,
-        districts_insert,
-        districts_delete,
-        regions_update,
-        regions_insert,
-        regions_delete,
+        read_history,
+        read_as_of,
+        insert_item,
+        update_item,
+        delete_item
     ];
-}
-
-#[derive(Debug, Copy, Clone)]
-enum Table {
-    Continents,
-    Cities,
-    Countries,
-    Districts,
-    Regions,
-}
-
-impl Table {
-    fn parse(name: String) -> Result<Self, Error> {
-        Ok(match name.split(".").next().take() {
-            Some("continents") => Self::Continents,
-            Some("cities") => Self::Cities,
-            Some("countries") => Self::Countries,
-            Some("districts") => Self::Districts,
-            Some("regions") => Self::Regions,
-            _ => return Err(Error::TableDoesntExistError { table: name }),
-        })
-    }
-}
-
-#[get("/<table_name>/items?<page_index>&<page_size>")]
+    pub static ref TABLES: TableRegistry = TableRegistry::new()
+        .register::<Continent>()
+        .register::<City>()
+        .register::<Country>()
+        .register::<District>()
+        .register::<Region>();
+}
+
+/// Pages are selected either by `page_index`, or by a `cursor` from a previous response,
+/// which is used when `page_index` is missing.
+#[get(
+    "/<table_name>/items?<page_index>&<page_size>&<cursor>&<with_count>&<sort_field>&<sort_order>&<filters..>"
+)]
+#[allow(clippy::too_many_arguments)]
 fn read_data(
     conn: OracleConnection,
     table_name: String,
-    mut page_index: usize,
+    page_index: Option<usize>,
     page_size: usize,
-    user: User,
+    cursor: Option<String>,
+    with_count: Option<bool>,
+    sort_field: Option<String>,
+    sort_order: Option<String>,
+    filters: FilterQuery,
+    page_config: State<PageConfig>,
+    grant: Grant<Read>,
 ) -> Result<JsonValue, Error> {
-    fn load_data_and_count_to_json<T>(
-        connection: &OracleConnection,
-        lower: usize,
-        higher: usize,
-    ) -> Result<JsonValue, Error>
-    where
-        T: SchemaTable + RowValue + Debug + Serialize,
-    {
-        let conn = &**connection;
-        let rows = count_rows::<T>(conn)?;
-        let data = load_data::<T>(conn, lower, higher)?;
-        Ok(json!({ "itemsCount" : rows, "data" : data}))
-    }
-
-    page_index -= 1;
-    let record_lower = page_index * page_size + 1;
-    let record_higher = page_size * (page_index + 1);
-    let table = Table::parse(table_name)?;
-    info!(
-        "{}",
-        format!(
-            "User {:?} read {:?} from {} to {}",
-            user, table, record_lower, record_higher
-        )
-    );
-    match table {
-        Table::Continents => {
-            load_data_and_count_to_json::<Continent>(&conn, record_lower, record_higher)
+    let table = TABLES.get(&table_name)?;
+    page_config.check(page_size)?;
+    let sort = SortQuery {
+        fields: sort_field,
+        orders: sort_order,
+    };
+    match page_index {
+        Some(page_index) => {
+            let (record_lower, record_higher) = page_records(page_index, page_size)?;
+            info!(
+                "{}",
+                format!(
+                    "User {:?} read {} from {} to {} with filters {:?} sorted by {:?}",
+                    grant.user, table_name, record_lower, record_higher, filters, sort
+                )
+            );
+            table.read(&*conn, record_lower, record_higher, &filters, &sort)
         }
-        Table::Cities => load_data_and_count_to_json::<City>(&conn, record_lower, record_higher),
-        Table::Countries => {
-            load_data_and_count_to_json::<Country>(&conn, record_lower, record_higher)
+        None => {
+            info!(
+                "{}",
+                format!(
+                    "User {:?} read {} rows of {} at cursor {:?} with filters {:?} sorted by {:?}",
+                    grant.user, page_size, table_name, cursor, filters, sort
+                )
+            );
+            table.read_page(
+                &*conn,
+                cursor.as_deref(),
+                page_size,
+                &filters,
+                &sort,
+                with_count.unwrap_or(false),
+            )
         }
-        Table::Districts => {
-            load_data_and_count_to_json::<District>(&conn, record_lower, record_higher)
-        }
-        Table::Regions => load_data_and_count_to_json::<Region>(&conn, record_lower, record_higher),
     }
 }
 
-#[post("/continents.tera/items", format = "json", data = "<item>")]
-fn continents_insert(
-    conn: OracleConnection,
-    item: Json<Continent>,
-    user: User,
-) -> Result<Json<Continent>, Error> {
-    info!(
-        "{}",
-        format!("User {:?} inserted continent {:?}", user, &item.0)
-    );
-    insert_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[post("/cities.tera/items", format = "json", data = "<item>")]
-fn cities_insert(
-    conn: OracleConnection,
-    item: Json<City>,
-    user: User,
-) -> Result<Json<City>, Error> {
-    info!("{}", format!("User {:?} inserted city {:?}", user, &item.0));
-    insert_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[post("/countries.tera/items", format = "json", data = "<item>")]
-fn countries_insert(
-    conn: OracleConnection,
-    item: Json<Country>,
-    user: User,
-) -> Result<Json<Country>, Error> {
-    info!(
-        "{}",
-        format!("User {:?} inserted country {:?}", user, &item.0)
-    );
-    insert_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[post("/districts.tera/items", format = "json", data = "<item>")]
-fn districts_insert(
-    conn: OracleConnection,
-    item: Json<District>,
-    user: User,
-) -> Result<Json<District>, Error> {
-    info!(
-        "{}",
-        format!("User {:?} inserted district {:?}", user, &item.0)
-    );
-    insert_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[post("/regions.tera/items", format = "json", data = "<item>")]
-fn regions_insert(
+/// Versions of the row with the key, from the changes in the audit log.
+#[get("/<table_name>/items/<key>/history")]
+fn read_history(
     conn: OracleConnection,
-    item: Json<Region>,
-    user: User,
-) -> Result<Json<Region>, Error> {
-    info!(
-        "{}",
-        format!("User {:?} inserted region {:?}", user, &item.0)
-    );
-    insert_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[put("/continents.tera/items", format = "json", data = "<item>")]
-fn continents_update(
-    conn: OracleConnection,
-    item: Json<OldNew<Continent>>,
-    user: Admin,
-) -> Result<Json<Continent>, Error> {
-    info!(
-        "{}",
-        format!(
-            "Admin {:?} updated continents row {:?} to {:?}",
-            user,
-            &(item.0).old,
-            &(item.0).new
-        )
-    );
-    update_data(&*conn, &(item.0).old, &(item.0).new)?;
-    Ok(Json(item.into_inner().new))
-}
-
-#[put("/cities.tera/items", format = "json", data = "<item>")]
-fn cities_update(
-    conn: OracleConnection,
-    item: Json<OldNew<City>>,
-    user: Admin,
-) -> Result<Json<City>, Error> {
+    table_name: String,
+    key: String,
+    grant: Grant<Read>,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
     info!(
         "{}",
         format!(
-            "Admin {:?} updated cities row {:?} to {:?}",
-            user,
-            &(item.0).old,
-            &(item.0).new
+            "User {:?} read the history of {} row {}",
+            grant.user, table_name, key
         )
     );
-    update_data(&*conn, &(item.0).old, &(item.0).new)?;
-    Ok(Json(item.into_inner().new))
+    table.history(&*conn, &key)
 }
 
-#[put("/countries.tera/items", format = "json", data = "<item>")]
-fn countries_update(
+/// A page of the table as it was at `time`, an RFC 3339 date like `2020-05-01T00:00:00Z`.
+#[get("/<table_name>/items/as_of?<time>&<page_index>&<page_size>")]
+fn read_as_of(
     conn: OracleConnection,
-    item: Json<OldNew<Country>>,
-    user: Admin,
-) -> Result<Json<Country>, Error> {
+    table_name: String,
+    time: String,
+    page_index: usize,
+    page_size: usize,
+    page_config: State<PageConfig>,
+    grant: Grant<Read>,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
+    page_config.check(page_size)?;
+    let (record_start, record_end) = page_records(page_index, page_size)?;
+    let time = DateTime::parse_from_rfc3339(&time)
+        .map_err(|_| Error::InvalidDateError {
+            value: time.clone(),
+        })?
+        .with_timezone(&Utc);
     info!(
         "{}",
         format!(
-            "Admin {:?} updated countries row {:?} to {:?}",
-            user,
-            &(item.0).old,
-            &(item.0).new
+            "User {:?} read {} from {} to {} as of {}",
+            grant.user, table_name, record_start, record_end, time
         )
     );
-    update_data(&*conn, &(item.0).old, &(item.0).new)?;
-    Ok(Json(item.into_inner().new))
+    table.as_of(&*conn, time, record_start, record_end)
 }
 
-#[put("/districts.tera/items", format = "json", data = "<item>")]
-fn districts_update(
+#[post("/<table_name>/items", format = "json", data = "<item>")]
+fn insert_item(
     conn: OracleConnection,
-    item: Json<OldNew<District>>,
-    user: Admin,
-) -> Result<Json<District>, Error> {
+    table_name: String,
+    item: Json<Value>,
+    grant: Grant<Insert>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
     info!(
         "{}",
-        format!(
-            "Admin {:?} updated districts row {:?} to {:?}",
-            user,
-            &(item.0).old,
-            &(item.0).new
-        )
+        format!("User {:?} inserted {} {}", grant.user, table_name, &item.0)
     );
-    update_data(&*conn, &(item.0).old, &(item.0).new)?;
-    Ok(Json(item.into_inner().new))
+    table.insert(&*conn, &grant.user.data.username, item.into_inner())
 }
 
-#[put("/regions.tera/items", format = "json", data = "<item>")]
-fn regions_update(
+#[put("/<table_name>/items", format = "json", data = "<item>")]
+fn update_item(
     conn: OracleConnection,
-    item: Json<OldNew<Region>>,
-    user: Admin,
-) -> Result<Json<Region>, Error> {
+    table_name: String,
+    item: Json<Value>,
+    grant: Grant<Update>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
     info!(
         "{}",
         format!(
-            "Admin {:?} updated regions row {:?} to {:?}",
-            user,
-            &(item.0).old,
-            &(item.0).new
+            "User {:?} updated {} row {} to {}",
+            grant.user, table_name, &item.0["old"], &item.0["new"]
         )
     );
-    update_data(&*conn, &(item.0).old, &(item.0).new)?;
-    Ok(Json(item.into_inner().new))
+    table.update(&*conn, &grant.user.data.username, item.into_inner())
 }
 
-#[delete("/continents.tera/items", format = "json", data = "<item>")]
-fn continents_delete(
+#[delete("/<table_name>/items", format = "json", data = "<item>")]
+fn delete_item(
     conn: OracleConnection,
-    item: Json<Continent>,
-    user: Admin,
-) -> Result<Json<Continent>, Error> {
-    info!(
-        "{}",
-        format!("Admin {:?} deleted continents {:?}", user, &item.0)
-    );
-    delete_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[delete("/cities.tera/items", format = "json", data = "<item>")]
-fn cities_delete(
-    conn: OracleConnection,
-    item: Json<City>,
-    user: Admin,
-) -> Result<Json<City>, Error> {
-    info!(
-        "{}",
-        format!("Admin {:?} deleted cities {:?}", user, &item.0)
-    );
-    delete_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[delete("/countries.tera/items", format = "json", data = "<item>")]
-fn countries_delete(
-    conn: OracleConnection,
-    item: Json<Country>,
-    user: Admin,
-) -> Result<Json<Country>, Error> {
-    info!(
-        "{}",
-        format!("Admin {:?} deleted countries {:?}", user, &item.0)
-    );
-    delete_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[delete("/districts.tera/items", format = "json", data = "<item>")]
-fn districts_delete(
-    conn: OracleConnection,
-    item: Json<District>,
-    user: Admin,
-) -> Result<Json<District>, Error> {
-    info!(
-        "{}",
-        format!("Admin {:?} deleted districts {:?}", user, &item.0)
-    );
-    delete_data(&*conn, &item.0)?;
-    Ok(item)
-}
-
-#[delete("/regions.tera/items", format = "json", data = "<item>")]
-fn regions_delete(
-    conn: OracleConnection,
-    item: Json<Region>,
-    user: Admin,
-) -> Result<Json<Region>, Error> {
+    table_name: String,
+    item: Json<Value>,
+    grant: Grant<Delete>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
     info!(
         "{}",
-        format!("Admin {:?} deleted regions {:?}", user, &item.0)
+        format!("User {:?} deleted {} {}", grant.user, table_name, &item.0)
     );
-    delete_data(&*conn, &item.0)?;
-    Ok(item)
+    table.delete(&*conn, &grant.user.data.username, item.into_inner())
 }
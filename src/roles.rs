@@ -0,0 +1,109 @@
+use crate::auth::{Admin, OPERATIONS};
+use crate::csrf::Csrf;
+use crate::database::OracleConnection;
+use crate::database_operations::{delete_role, get_roles, set_role_grants};
+use crate::error::Error;
+use crate::read_insert_update_delete::TABLES;
+use crate::schema::{Role, RoleGrant};
+use crate::table_registry::table_name;
+use log::info;
+use rocket::Route;
+use rocket_contrib::json::{Json, JsonValue};
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    pub static ref ROLE_ROUTES: Vec<Route> = routes![list_roles, change_role, remove_role];
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GrantData {
+    /// Table name, or `*` for every table.
+    pub table: String,
+    pub operation: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RoleGrants {
+    pub grants: Vec<GrantData>,
+}
+
+fn role_json(role: &Role, grants: &[RoleGrant]) -> JsonValue {
+    json!({
+        "name": role.name,
+        "grants": grants
+            .iter()
+            .map(|g| GrantData {
+                table: g.table_name.clone(),
+                operation: g.operation.clone(),
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[get("/admin/roles")]
+fn list_roles(conn: OracleConnection, admin: Admin) -> Result<JsonValue, Error> {
+    info!("{}", format!("Admin {:?} listed roles", admin));
+    Ok(json!(get_roles(&*conn)?
+        .iter()
+        .map(|(role, grants)| role_json(role, grants).0)
+        .collect::<Vec<_>>()))
+}
+
+/// Creates the role or replaces its grants.
+#[put("/admin/roles/<name>", format = "json", data = "<grants>")]
+fn change_role(
+    conn: OracleConnection,
+    name: String,
+    grants: Json<RoleGrants>,
+    admin: Admin,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if name.trim().is_empty() {
+        return Err(Error::UserManagementError {
+            message: "role name can't be empty".to_owned(),
+        });
+    }
+    let role = Role { name };
+    let grants = grants
+        .into_inner()
+        .grants
+        .into_iter()
+        .map(|g| {
+            if !OPERATIONS.contains(&g.operation.as_str()) {
+                return Err(Error::UserManagementError {
+                    message: format!("unknown operation {:?}", g.operation),
+                });
+            }
+            if g.table != "*" {
+                TABLES.get(&g.table)?;
+            }
+            Ok(RoleGrant {
+                role_name: role.name.clone(),
+                table_name: table_name(&g.table).to_owned(),
+                operation: g.operation,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    info!(
+        "{}",
+        format!(
+            "Admin {:?} set grants of role {} to {:?}",
+            admin, role.name, grants
+        )
+    );
+    set_role_grants(&*conn, &role, &grants)?;
+    Ok(role_json(&role, &grants))
+}
+
+#[delete("/admin/roles/<name>")]
+fn remove_role(
+    conn: OracleConnection,
+    name: String,
+    admin: Admin,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    info!("{}", format!("Admin {:?} deleted role {}", admin, name));
+    let role = Role { name };
+    delete_role(&*conn, &role)?;
+    Ok(json!({ "name": role.name }))
+}
@@ -1,271 +1,236 @@
-use crate::database_operations::SchemaTable;
 use chrono;
-use r2d2_oracle::oracle::{
-    sql_type::{OracleType, ToSql},
-    Error, Row, RowValue,
-};
+use schema_derive::{RowValue, SchemaTable};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("continents")]
 pub struct Continent {
+    #[key]
     pub name: String,
     pub area_m2: f64,
 }
-impl RowValue for Continent {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(Continent {
-            name: row.get("name")?,
-            area_m2: row.get("area_m2")?,
-        })
-    }
-}
-impl SchemaTable for Continent {
-    fn column_names() -> Vec<&'static str> {
-        vec!["name", "area_m2"]
-    }
-
-    fn table_name() -> &'static str {
-        "continents"
-    }
-
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.name.clone()), Box::new(self.area_m2.clone())]
-    }
-
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["name"]
-    }
-
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.name.clone())]
-    }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("cities")]
 pub struct City {
+    #[key]
     pub city_id: usize,
     pub city_name: String,
+    #[references("regions.region_id")]
     pub fg_region_id: Option<usize>,
 }
-impl RowValue for City {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(City {
-            city_id: row.get("city_id")?,
-            city_name: row.get("city_name")?,
-            fg_region_id: row.get("fg_region_id")?,
-        })
-    }
-}
-impl SchemaTable for City {
-    fn column_names() -> Vec<&'static str> {
-        vec!["city_id", "city_name", "fg_region_id"]
-    }
-
-    fn table_name() -> &'static str {
-        "cities"
-    }
-
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![
-            Box::new(self.city_id.clone()),
-            Box::new(self.city_name.clone()),
-            Box::new(self.fg_region_id.clone()),
-        ]
-    }
-
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["city_id"]
-    }
-
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.city_id.clone())]
-    }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("countries")]
 pub struct Country {
+    #[key]
     pub name: String,
+    #[references("continents.name")]
     pub fg_continent_name: Option<String>,
+    #[references("cities.city_id")]
     pub fg_capital_city_id: Option<usize>,
 }
-impl RowValue for Country {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(Country {
-            name: row.get("name")?,
-            fg_continent_name: row.get("fg_continent_name")?,
-            fg_capital_city_id: row.get("fg_capital_city_id")?,
-        })
-    }
-}
-impl SchemaTable for Country {
-    fn column_names() -> Vec<&'static str> {
-        vec!["name", "fg_continent_name", "fg_capital_city_id"]
-    }
-
-    fn table_name() -> &'static str {
-        "countries"
-    }
 
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![
-            Box::new(self.name.clone()),
-            Box::new(self.fg_continent_name.clone()),
-            Box::new(self.fg_capital_city_id.clone()),
-        ]
-    }
-
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["name"]
-    }
-
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.name.clone())]
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("districts")]
 pub struct District {
+    #[key]
     pub district_id: usize,
     pub district_name: String,
+    #[references("cities.city_id")]
     pub fg_city_id: Option<usize>,
 }
-impl RowValue for District {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(District {
-            district_id: row.get("district_id")?,
-            district_name: row.get("district_name")?,
-            fg_city_id: row.get("fg_city_id")?,
-        })
-    }
-}
-impl SchemaTable for District {
-    fn column_names() -> Vec<&'static str> {
-        vec!["district_id", "district_name", "fg_city_id"]
-    }
-
-    fn table_name() -> &'static str {
-        "districts"
-    }
-
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![
-            Box::new(self.district_id.clone()),
-            Box::new(self.district_name.clone()),
-            Box::new(self.fg_city_id.clone()),
-        ]
-    }
-
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["district_id"]
-    }
-
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.district_id.clone())]
-    }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("regions")]
 pub struct Region {
+    #[key]
     pub region_id: usize,
     pub region_name: String,
+    #[references("countries.name")]
     pub fg_country_name: Option<String>,
+    #[column(digits = 12)]
     pub population: usize,
     pub area_m2: f64,
     pub climate: String,
+    #[references("cities.city_id")]
     pub fg_centre_city_id: Option<usize>,
 }
-impl RowValue for Region {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(Region {
-            region_id: row.get("region_id")?,
-            region_name: row.get("region_name")?,
-            fg_country_name: row.get("fg_country_name")?,
-            population: row.get("population")?,
-            area_m2: row.get("area_m2")?,
-            climate: row.get("climate")?,
-            fg_centre_city_id: row.get("fg_centre_city_id")?,
-        })
-    }
-}
-impl SchemaTable for Region {
-    fn column_names() -> Vec<&'static str> {
-        vec![
-            "region_id",
-            "region_name",
-            "fg_country_name",
-            "population",
-            "area_m2",
-            "climate",
-            "fg_centre_city_id",
-        ]
-    }
-
-    fn table_name() -> &'static str {
-        "regions"
-    }
-
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![
-            Box::new(self.region_id.clone()),
-            Box::new(self.region_name.clone()),
-            Box::new(self.fg_country_name.clone()),
-            Box::new(self.population.clone()),
-            Box::new(self.area_m2.clone()),
-            Box::new(self.climate.clone()),
-            Box::new(self.fg_centre_city_id.clone()),
-        ]
-    }
-
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["region_id"]
-    }
 
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.region_id.clone())]
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone, SmartDefault)]
+#[derive(Serialize, Deserialize, Debug, Clone, SmartDefault, SchemaTable, RowValue)]
+#[table("users_info")]
 pub struct UserInfo {
+    #[key]
     pub username: String,
+    /// Argon2 hash, see `password`.
+    #[serde(skip_serializing)]
+    #[column(len = 200)]
     pub password: String,
+    #[column(len = 1, fixed, default = "'n'")]
     pub is_admin: String,
     #[default(_code = "chrono::Utc::now()")]
+    #[column(default_now)]
     pub last_appearance: chrono::DateTime<chrono::Utc>,
+    /// `y` if the user isn't allowed to log in.
+    #[default(_code = "\"n\".to_owned()")]
+    #[column(len = 1, fixed, default = "'n'")]
+    pub is_disabled: String,
+    #[default(_code = "chrono::Utc::now()")]
+    #[column(default_now, sqlite_default = "'1970-01-01T00:00:00Z'")]
+    pub password_changed_at: chrono::DateTime<chrono::Utc>,
 }
-impl RowValue for UserInfo {
-    fn get(row: &Row) -> Result<Self, Error> {
-        Ok(UserInfo {
-            username: row.get("username")?,
-            password: row.get("password")?,
-            is_admin: row.get("is_admin")?,
-            last_appearance: row.get("last_appearance")?,
-        })
-    }
-}
-impl SchemaTable for UserInfo {
-    fn column_names() -> Vec<&'static str> {
-        vec!["username", "password", "is_admin", "last_appearance"]
-    }
-
-    fn table_name() -> &'static str {
-        "users_info"
-    }
 
-    fn values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![
-            Box::new(self.username.clone()),
-            Box::new(self.password.clone()),
-            Box::new(self.is_admin.clone()),
-            Box::new(self.last_appearance.clone()),
-        ]
-    }
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("roles")]
+pub struct Role {
+    #[key]
+    pub name: String,
+}
 
-    fn key_attrs() -> Vec<&'static str> {
-        vec!["username"]
-    }
+/// Allows the users of a role to do `operation` on `table_name`, or on every table for `*`.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("role_grants")]
+pub struct RoleGrant {
+    #[key]
+    #[references("roles.name")]
+    pub role_name: String,
+    #[key]
+    pub table_name: String,
+    #[key]
+    pub operation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("user_roles")]
+pub struct UserRole {
+    #[key]
+    #[references("users_info.username")]
+    pub username: String,
+    #[key]
+    #[references("roles.name")]
+    pub role_name: String,
+}
+
+/// Personal token for the `Authorization: Bearer` header.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("api_tokens")]
+pub struct ApiToken {
+    #[key]
+    pub token_id: String,
+    #[references("users_info.username")]
+    pub username: String,
+    /// Hex encoded sha-256 of the token.
+    #[serde(skip_serializing)]
+    #[column(len = 64, unique)]
+    pub token_hash: String,
+    pub name: String,
+    /// `read` or `read_write`.
+    pub scope: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Login session, the cookie holds the id the key is hashed from.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("sessions")]
+pub struct Session {
+    #[key]
+    pub session_id: String,
+    #[references("users_info.username")]
+    pub username: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen: chrono::DateTime<chrono::Utc>,
+    #[column(len = 500)]
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Failed logins of a username or of a client address.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("login_failures")]
+pub struct LoginFailure {
+    #[key]
+    #[column(len = 200)]
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure: chrono::DateTime<chrono::Utc>,
+    pub locked_until: chrono::DateTime<chrono::Utc>,
+}
+
+/// TOTP secret of a user with two-factor authentication.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("user_totp")]
+pub struct UserTotp {
+    #[key]
+    #[references("users_info.username")]
+    pub username: String,
+    #[serde(skip_serializing)]
+    #[column(len = 64)]
+    pub secret: String,
+    #[column(len = 1, fixed, default = "'n'")]
+    pub is_confirmed: String,
+    #[column(digits = 12)]
+    pub last_step: Option<i64>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("recovery_codes")]
+pub struct RecoveryCode {
+    #[key]
+    #[references("users_info.username")]
+    pub username: String,
+    #[key]
+    #[column(len = 64)]
+    pub code_hash: String,
+}
 
-    fn key_attr_values(&self) -> Vec<Box<dyn ToSql>> {
-        vec![Box::new(self.username.clone())]
-    }
+/// Previous password of a user, which can't be used again.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("password_history")]
+pub struct PasswordHistory {
+    #[key]
+    #[references("users_info.username")]
+    pub username: String,
+    #[key]
+    #[serde(skip_serializing)]
+    #[column(len = 200)]
+    pub password: String,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A change made through `insert_data`, `update_data` or `delete_data`,
+/// with the row as JSON before and after it.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("audit_log")]
+pub struct AuditEntry {
+    #[key]
+    pub entry_id: String,
+    pub actor: String,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+    pub table_name: String,
+    /// `insert`, `update` or `delete`.
+    #[column(len = 20)]
+    pub operation: String,
+    #[column(len = 1000)]
+    pub row_key: String,
+    #[column(len = 4000)]
+    pub old_value: Option<String>,
+    #[column(len = 4000)]
+    pub new_value: Option<String>,
+}
+
+/// A row deleted from a table page, which admins can restore.
+#[derive(Serialize, Deserialize, Debug, Clone, SchemaTable, RowValue)]
+#[table("trash")]
+pub struct TrashEntry {
+    #[key]
+    pub trash_id: String,
+    pub table_name: String,
+    #[column(len = 1000)]
+    pub row_key: String,
+    #[column(len = 4000)]
+    pub row_value: String,
+    pub deleted_by: String,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
 }
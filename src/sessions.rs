@@ -0,0 +1,216 @@
+//! Login sessions stored in the `sessions` table.
+//!
+//! The private session cookie holds a random id and the table is keyed by its sha-256, so
+//! a session ends when its row is deleted, on any device. Sessions expire after
+//! `session_idle_timeout` minutes without requests and `session_absolute_timeout` minutes
+//! after login, both set in `Rocket.toml`.
+
+use crate::auth::{Admin, User};
+use crate::csrf::Csrf;
+use crate::database::{DbConnection, OracleConnection};
+use crate::database_operations::{
+    delete_data, delete_user_sessions, get_data, get_sessions, insert_data,
+};
+use crate::error::Error;
+use crate::schema::Session;
+use crate::tokens::{hash, random_string};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use rocket::config::ConfigError;
+use rocket::fairing::AdHoc;
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request, Route};
+use rocket_contrib::json::JsonValue;
+
+lazy_static! {
+    pub static ref SESSION_ROUTES: Vec<Route> = routes![
+        list_sessions,
+        list_all_sessions,
+        revoke_session,
+        revoke_own_sessions
+    ];
+}
+
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// Timeouts from `Rocket.toml`, managed by `config_fairing`.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionConfig {
+    pub idle_timeout: Duration,
+    pub absolute_timeout: Duration,
+}
+
+impl SessionConfig {
+    pub fn has_expired(&self, session: &Session, now: DateTime<Utc>) -> bool {
+        session.last_seen + self.idle_timeout < now
+            || session.created_at + self.absolute_timeout < now
+    }
+}
+
+/// Reads the session timeouts, in minutes. They default to the 5 minutes idle timeout
+/// sessions always had and to 12 hours since login.
+pub fn config_fairing() -> AdHoc {
+    AdHoc::on_attach("Session timeouts", |rocket| {
+        let minutes = |name: &str, default: i64| match rocket.config().get_int(name) {
+            Ok(value) if value > 0 => Ok(Duration::minutes(value)),
+            Ok(_) => Err(format!("{} has to be positive", name)),
+            Err(ConfigError::Missing(_)) => Ok(Duration::minutes(default)),
+            Err(e) => Err(e.to_string()),
+        };
+        let config = minutes("session_idle_timeout", 5).and_then(|idle_timeout| {
+            Ok(SessionConfig {
+                idle_timeout,
+                absolute_timeout: minutes("session_absolute_timeout", 12 * 60)?,
+            })
+        });
+        match config {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                error!("Invalid session configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Where a login comes from, shown in the list of sessions.
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            // long enough to recognize the browser, and to fit the column
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|agent| agent.chars().take(200).collect()),
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
+
+/// Creates a session for the user and returns the id for the session cookie.
+pub fn start(
+    connection: &DbConnection,
+    username: &str,
+    client: ClientInfo,
+) -> Result<String, Error> {
+    let id = random_string(32);
+    let now = Utc::now();
+    insert_data(
+        connection,
+        None,
+        &Session {
+            session_id: hash(&id),
+            username: username.to_owned(),
+            created_at: now,
+            last_seen: now,
+            user_agent: client.user_agent,
+            ip_address: client.ip_address,
+        },
+    )?;
+    Ok(id)
+}
+
+/// The stored session of the cookie value, whether it has expired or not.
+pub fn find(connection: &DbConnection, id: &str) -> Result<Option<Session>, Error> {
+    find_by_key(connection, hash(id))
+}
+
+fn find_by_key(connection: &DbConnection, session_id: String) -> Result<Option<Session>, Error> {
+    get_data(
+        connection,
+        &Session {
+            session_id,
+            username: String::new(),
+            created_at: Utc::now(),
+            last_seen: Utc::now(),
+            user_agent: None,
+            ip_address: None,
+        },
+    )
+}
+
+fn rejected(message: &str) -> Error {
+    Error::UserManagementError {
+        message: message.to_owned(),
+    }
+}
+
+fn sessions_json(user: &User, sessions: Vec<Session>) -> JsonValue {
+    let data = sessions
+        .into_iter()
+        .map(|session| {
+            let current = user.session.as_ref() == Some(&session.session_id);
+            let mut json = json!(session);
+            json["current"] = current.into();
+            json.0
+        })
+        .collect::<Vec<_>>();
+    json!({ "data": data })
+}
+
+#[get("/sessions")]
+fn list_sessions(conn: OracleConnection, user: User) -> Result<JsonValue, Error> {
+    let sessions = get_sessions(&*conn, Some(&user.data.username))?;
+    Ok(sessions_json(&user, sessions))
+}
+
+#[get("/admin/sessions")]
+fn list_all_sessions(conn: OracleConnection, user: User, admin: Admin) -> Result<JsonValue, Error> {
+    info!("{}", format!("Admin {:?} listed sessions", admin));
+    Ok(sessions_json(&user, get_sessions(&*conn, None)?))
+}
+
+/// Ends one of the user's sessions, or anyone's for admins.
+// ranked after `DELETE /<table_name>/items`, which matches the same paths
+#[delete("/sessions/<session_id>", rank = 2)]
+fn revoke_session(
+    conn: OracleConnection,
+    session_id: String,
+    user: User,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if !user.can_write() {
+        return Err(rejected("read-only tokens can't revoke sessions"));
+    }
+    let session = find_by_key(&*conn, session_id)?
+        // other users' sessions look the same as missing ones
+        .filter(|session| session.username == user.data.username || user.is_admin)
+        .ok_or_else(|| Error::KeyDoesntExistError {
+            table_name: "sessions".to_owned(),
+        })?;
+    info!(
+        "{}",
+        format!(
+            "User {:?} revoked a session of {:?}",
+            user.data.username, session.username
+        )
+    );
+    delete_data(&*conn, None, &session)?;
+    Ok(json!(session))
+}
+
+/// Signs the user out on every device.
+#[delete("/sessions")]
+fn revoke_own_sessions(
+    conn: OracleConnection,
+    user: User,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if !user.can_write() {
+        return Err(rejected("read-only tokens can't revoke sessions"));
+    }
+    info!(
+        "{}",
+        format!("User {:?} revoked all their sessions", user.data.username)
+    );
+    delete_user_sessions(&*conn, &user.data.username)?;
+    Ok(json!({}))
+}
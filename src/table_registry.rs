@@ -0,0 +1,387 @@
+use crate::auth::{Delete, Insert, Operation, Update};
+use crate::backend::RowValue;
+use crate::core::OldNew;
+use crate::cursor::load_page;
+use crate::database::DbConnection;
+use crate::database_operations::{
+    count_rows, insert_data, insert_row, load_data, missing_references, restore_data, trash_data,
+    trash_row, update_data, update_row, SchemaTable,
+};
+use crate::error::Error;
+use crate::filter::{conditions, sort_columns, FilterQuery, SortQuery};
+use crate::history::{row_history, table_as_of};
+use crate::schema::TrashEntry;
+use chrono::{DateTime, Utc};
+use rocket_contrib::json::JsonValue;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// Operations on a table with rows passed as JSON, so that routes can serve a table
+/// picked by name at runtime.
+pub trait TableHandler: Send + Sync {
+    /// Rows `record_start..=record_end` of the rows matching `filters` sorted by `sort`,
+    /// along with the number of matching rows.
+    fn read(
+        &self,
+        connection: &DbConnection,
+        record_start: usize,
+        record_end: usize,
+        filters: &FilterQuery,
+        sort: &SortQuery,
+    ) -> Result<JsonValue, Error>;
+
+    /// Up to `page_size` rows matching `filters` after the row of `cursor`, with the cursors
+    /// of the next and previous pages and, if asked for, the number of matching rows.
+    fn read_page(
+        &self,
+        connection: &DbConnection,
+        cursor: Option<&str>,
+        page_size: usize,
+        filters: &FilterQuery,
+        sort: &SortQuery,
+        with_count: bool,
+    ) -> Result<JsonValue, Error>;
+
+    /// `actor` is the user making the change, recorded in `audit_log`.
+    fn insert(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error>;
+
+    /// `item` is an `OldNew` of the table's rows.
+    fn update(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error>;
+
+    /// Deletes the row and keeps it in the trash.
+    fn delete(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error>;
+
+    /// Inserts, updates or deletes like the methods above without committing, so that
+    /// the changes of a batch can be committed together.
+    fn apply(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        operation: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error>;
+
+    /// Inserts the row of the trash entry again, if the rows it references still exist.
+    fn restore(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        entry: &TrashEntry,
+    ) -> Result<JsonValue, Error>;
+
+    /// Undoes the last change of the row with the key given in a url, which has to be
+    /// an update.
+    fn revert(&self, connection: &DbConnection, actor: &str, key: &str)
+        -> Result<JsonValue, Error>;
+
+    /// Versions of the row with the key given in a url, oldest first.
+    fn history(&self, connection: &DbConnection, key: &str) -> Result<JsonValue, Error>;
+
+    /// The rows from `record_start` to `record_end` as they were at the time.
+    fn as_of(
+        &self,
+        connection: &DbConnection,
+        time: DateTime<Utc>,
+        record_start: usize,
+        record_end: usize,
+    ) -> Result<JsonValue, Error>;
+}
+
+struct Table<T>(PhantomData<fn() -> T>);
+
+impl<T> TableHandler for Table<T>
+where
+    T: SchemaTable + RowValue + Serialize + DeserializeOwned + Debug,
+{
+    fn read(
+        &self,
+        connection: &DbConnection,
+        record_start: usize,
+        record_end: usize,
+        filters: &FilterQuery,
+        sort: &SortQuery,
+    ) -> Result<JsonValue, Error> {
+        let conditions = conditions::<T>(filters)?;
+        let sort = sort_columns::<T>(sort)?;
+        let rows = count_rows::<T>(connection, &conditions)?;
+        let data = load_data::<T>(connection, record_start, record_end, &conditions, &sort)?;
+        Ok(json!({ "itemsCount" : rows, "data" : data}))
+    }
+
+    fn read_page(
+        &self,
+        connection: &DbConnection,
+        cursor: Option<&str>,
+        page_size: usize,
+        filters: &FilterQuery,
+        sort: &SortQuery,
+        with_count: bool,
+    ) -> Result<JsonValue, Error> {
+        let conditions = conditions::<T>(filters)?;
+        let sort = sort_columns::<T>(sort)?;
+        let page = load_page::<T>(connection, &conditions, &sort, cursor, page_size)?;
+        let mut result = json!({ "data" : page.data, "next" : page.next, "prev" : page.prev });
+        if with_count {
+            result["itemsCount"] = json!(count_rows::<T>(connection, &conditions)?).0;
+        }
+        Ok(result)
+    }
+
+    fn insert(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error> {
+        let item = serde_json::from_value::<T>(item)?;
+        Ok(json!(insert_data(connection, Some(actor), &item)?))
+    }
+
+    fn update(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error> {
+        let item = serde_json::from_value::<OldNew<T>>(item)?;
+        Ok(json!(update_data(
+            connection,
+            Some(actor),
+            &item.old,
+            &item.new
+        )?))
+    }
+
+    fn delete(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error> {
+        let item = serde_json::from_value::<T>(item)?;
+        trash_data(connection, actor, &item)?;
+        Ok(json!(item))
+    }
+
+    fn apply(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        operation: &str,
+        item: Value,
+    ) -> Result<JsonValue, Error> {
+        match operation {
+            Insert::NAME => {
+                let item = serde_json::from_value::<T>(item)?;
+                Ok(json!(insert_row(connection, Some(actor), &item)?))
+            }
+            Update::NAME => {
+                let item = serde_json::from_value::<OldNew<T>>(item)?;
+                Ok(json!(update_row(
+                    connection,
+                    Some(actor),
+                    &item.old,
+                    &item.new
+                )?))
+            }
+            Delete::NAME => {
+                let item = serde_json::from_value::<T>(item)?;
+                trash_row(connection, actor, &item)?;
+                Ok(json!(item))
+            }
+            _ => Err(Error::InvalidBatchError {
+                reason: format!("unknown operation {:?}", operation),
+            }),
+        }
+    }
+
+    fn restore(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        entry: &TrashEntry,
+    ) -> Result<JsonValue, Error> {
+        let row = serde_json::from_str::<T>(&entry.row_value)?;
+        let restored = restore_data(connection, actor, entry, &row)
+            .or_else(|e| references_conflict(connection, &row, e))?;
+        Ok(json!(restored))
+    }
+
+    fn revert(
+        &self,
+        connection: &DbConnection,
+        actor: &str,
+        key: &str,
+    ) -> Result<JsonValue, Error> {
+        let mut versions = row_history::<T>(connection, key)?;
+        let (current, previous) = match (versions.pop(), versions.pop()) {
+            (Some(current), Some(previous)) if current.operation.as_deref() == Some("update") => {
+                (current.row, previous.row)
+            }
+            (current, _) => {
+                return Err(Error::ConflictError {
+                    message: "only an update can be reverted".to_owned(),
+                    current: current.and_then(|version| version.row),
+                })
+            }
+        };
+        let (current, previous) = match (current, previous) {
+            (Some(current), Some(previous)) => (
+                serde_json::from_value::<T>(current)?,
+                serde_json::from_value::<T>(previous)?,
+            ),
+            _ => {
+                return Err(Error::KeyDoesntExistError {
+                    table_name: T::table_name().to_owned(),
+                })
+            }
+        };
+        let reverted = update_data(connection, Some(actor), &current, &previous)
+            .or_else(|e| references_conflict(connection, &previous, e))?;
+        Ok(json!(reverted))
+    }
+
+    fn history(&self, connection: &DbConnection, key: &str) -> Result<JsonValue, Error> {
+        Ok(json!({ "data": row_history::<T>(connection, key)? }))
+    }
+
+    fn as_of(
+        &self,
+        connection: &DbConnection,
+        time: DateTime<Utc>,
+        record_start: usize,
+        record_end: usize,
+    ) -> Result<JsonValue, Error> {
+        let (rows, data) = table_as_of::<T>(connection, time, record_start, record_end)?;
+        Ok(json!({ "as_of": time, "itemsCount": rows, "data": data }))
+    }
+}
+
+/// Rows can only come back if the rows they reference exist, which the foreign keys of
+/// the tables check when the row is written. Their violation becomes a conflict that names
+/// the references missing after the rollback of the write, if they are still missing.
+fn references_conflict<T>(connection: &DbConnection, row: &T, error: Error) -> Result<T, Error>
+where
+    T: SchemaTable,
+{
+    if !error.is_foreign_key_violation() {
+        return Err(error);
+    }
+    let missing = missing_references(connection, row)?;
+    Err(Error::ConflictError {
+        message: if missing.is_empty() {
+            "a referenced row was deleted meanwhile".to_owned()
+        } else {
+            format!("the referenced rows don't exist: {}", missing.join(", "))
+        },
+        current: None,
+    })
+}
+
+/// Table name from the name used in urls, e.g. `regions` for `regions.tera`.
+pub fn table_name(url_name: &str) -> &str {
+    url_name.split('.').next().unwrap_or(url_name)
+}
+
+/// Tables served by the CRUD routes, keyed by `SchemaTable::table_name`.
+#[derive(Default)]
+pub struct TableRegistry {
+    tables: HashMap<&'static str, Box<dyn TableHandler>>,
+}
+
+impl TableRegistry {
+    pub fn new() -> Self {
+        TableRegistry::default()
+    }
+
+    pub fn register<T>(mut self) -> Self
+    where
+        T: SchemaTable + RowValue + Serialize + DeserializeOwned + Debug + 'static,
+    {
+        self.tables
+            .insert(T::table_name(), Box::new(Table::<T>(PhantomData)));
+        self
+    }
+
+    /// Finds a table by the name used in urls, e.g. `regions.tera` or `regions`.
+    pub fn get(&self, name: &str) -> Result<&dyn TableHandler, Error> {
+        self.tables
+            .get(table_name(name))
+            .map(|table| table.as_ref())
+            .ok_or_else(|| Error::TableDoesntExistError {
+                table: name.to_owned(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_operations::{delete_data, get_data};
+    use crate::schema::{City, Region};
+    use crate::test_database;
+
+    fn check_restore_without_reference(connection: &DbConnection) {
+        let registry = TableRegistry::new().register::<City>();
+        let region = Region {
+            region_id: 1,
+            region_name: "Region".to_owned(),
+            fg_country_name: None,
+            population: 1,
+            area_m2: 1.5,
+            climate: "mild".to_owned(),
+            fg_centre_city_id: None,
+        };
+        let city = City {
+            city_id: 1,
+            city_name: "City".to_owned(),
+            fg_region_id: Some(1),
+        };
+        insert_data(connection, None, &region).unwrap();
+        insert_data(connection, None, &city).unwrap();
+        let entry = trash_data(connection, "admin", &city).unwrap();
+        delete_data(connection, None, &region).unwrap();
+
+        let restored = registry
+            .get("cities")
+            .unwrap()
+            .restore(connection, "admin", &entry);
+        match restored {
+            Err(Error::ConflictError { message, .. }) => {
+                assert!(message.contains("regions.region_id"), "{}", message)
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(get_data(connection, &city).unwrap().is_none());
+    }
+
+    #[test]
+    fn sqlite_restore_without_reference_conflicts() {
+        check_restore_without_reference(&test_database::sqlite());
+    }
+
+    #[test]
+    fn postgres_restore_without_reference_conflicts() {
+        if let Some(database) = test_database::postgres() {
+            check_restore_without_reference(&database);
+        }
+    }
+}
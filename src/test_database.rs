@@ -0,0 +1,68 @@
+//! Migrated databases for tests: SQLite in memory, and Postgres in a schema of its own
+//! when `TEST_POSTGRES_URL` is set, e.g. to `host=localhost user=postgres`.
+
+use crate::database::{DbConnection, DbConnectionManager};
+use crate::migrations::migrate_up;
+use r2d2_oracle::r2d2::ManageConnection;
+use std::ops::Deref;
+
+pub fn empty_sqlite() -> DbConnection {
+    DbConnectionManager::Sqlite(":memory:".to_owned())
+        .connect()
+        .expect("SQLite opens in memory")
+}
+
+pub fn sqlite() -> DbConnection {
+    let connection = empty_sqlite();
+    migrate_up(&connection).expect("migrations apply");
+    connection
+}
+
+/// Connection to a Postgres schema, which is dropped with it.
+pub struct PostgresSchema {
+    connection: DbConnection,
+    schema: String,
+}
+
+impl Deref for PostgresSchema {
+    type Target = DbConnection;
+
+    fn deref(&self) -> &DbConnection {
+        &self.connection
+    }
+}
+
+impl Drop for PostgresSchema {
+    fn drop(&mut self) {
+        let backend = self.connection.backend();
+        let _ = backend.rollback();
+        let _ = backend.execute(&format!("drop schema {} cascade", self.schema), &[]);
+        let _ = backend.commit();
+    }
+}
+
+/// `None` if `TEST_POSTGRES_URL` isn't set, so that the tests can be run without Postgres.
+pub fn empty_postgres() -> Option<PostgresSchema> {
+    let url = std::env::var("TEST_POSTGRES_URL").ok()?;
+    let connection = DbConnectionManager::Postgres(url)
+        .connect()
+        .expect("TEST_POSTGRES_URL connects");
+    let schema = format!("test_{:016x}", rand::random::<u64>());
+    let backend = connection.backend();
+    for statement in &[
+        format!("create schema {}", schema),
+        format!("set search_path to {}", schema),
+    ] {
+        backend
+            .execute(statement, &[])
+            .expect("test schema is created");
+    }
+    backend.commit().expect("test schema is created");
+    Some(PostgresSchema { connection, schema })
+}
+
+pub fn postgres() -> Option<PostgresSchema> {
+    let database = empty_postgres()?;
+    migrate_up(&database).expect("migrations apply");
+    Some(database)
+}
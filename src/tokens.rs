@@ -0,0 +1,169 @@
+//! Personal API tokens for scripts, sent as `Authorization: Bearer <token>`.
+//!
+//! Only the sha-256 of a token is stored, the token itself is returned once when it's
+//! created. Tokens are random, so a fast hash is enough to look them up by.
+
+use crate::auth::User;
+use crate::csrf::Csrf;
+use crate::database::OracleConnection;
+use crate::database_operations::{delete_data, get_data, get_user_tokens, insert_data};
+use crate::error::Error;
+use crate::schema::ApiToken;
+use chrono::{Duration, Utc};
+use log::info;
+use rand::Rng;
+use rocket::Route;
+use rocket_contrib::json::{Json, JsonValue};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+lazy_static! {
+    pub static ref TOKEN_ROUTES: Vec<Route> = routes![list_tokens, create_token, revoke_token];
+}
+
+/// What a token may be used for, stored in `api_tokens.scope`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenScope {
+    /// Only reading rows, whatever the roles of the user allow.
+    Read,
+    /// Everything the roles of the user allow.
+    ReadWrite,
+}
+
+impl TokenScope {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::ReadWrite => "read_write",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [TokenScope::Read, TokenScope::ReadWrite]
+            .iter()
+            .copied()
+            .find(|scope| scope.name() == name)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewToken {
+    pub name: String,
+    pub scope: String,
+    /// Tokens without an expiry are valid until they are revoked.
+    pub expires_in_days: Option<i64>,
+}
+
+/// Hex encoded sha-256 of the token, as stored in `api_tokens.token_hash`.
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Random url safe string of `bytes` random bytes.
+pub fn random_string(bytes: usize) -> String {
+    let random = (0..bytes)
+        .map(|_| rand::thread_rng().gen::<u8>())
+        .collect::<Vec<_>>();
+    base64::encode_config(random, base64::URL_SAFE_NO_PAD)
+}
+
+fn rejected(message: &str) -> Error {
+    Error::UserManagementError {
+        message: message.to_owned(),
+    }
+}
+
+/// Tokens can only be managed from a login session, so that a leaked token
+/// can't be used to create more of them.
+fn check_session(user: &User) -> Result<(), Error> {
+    match user.token {
+        Some(_) => Err(rejected("API tokens can't be managed with an API token")),
+        None => Ok(()),
+    }
+}
+
+#[get("/tokens")]
+fn list_tokens(conn: OracleConnection, user: User) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    Ok(json!({ "data": get_user_tokens(&*conn, &user.data.username)? }))
+}
+
+#[post("/tokens", format = "json", data = "<token>")]
+fn create_token(
+    conn: OracleConnection,
+    token: Json<NewToken>,
+    user: User,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    if token.name.trim().is_empty() {
+        return Err(rejected("token name can't be empty"));
+    }
+    let scope =
+        TokenScope::from_name(&token.scope).ok_or_else(|| rejected("unknown token scope"))?;
+    if token.expires_in_days.map_or(false, |days| days <= 0) {
+        return Err(rejected("token has to expire in at least one day"));
+    }
+    let secret = random_string(32);
+    let now = Utc::now();
+    let created = insert_data(
+        &*conn,
+        Some(&user.data.username),
+        &ApiToken {
+            token_id: random_string(9),
+            username: user.data.username.clone(),
+            token_hash: hash(&secret),
+            name: token.name.trim().to_owned(),
+            scope: scope.name().to_owned(),
+            created_at: now,
+            expires_at: token.expires_in_days.map(|days| now + Duration::days(days)),
+        },
+    )?;
+    info!(
+        "{}",
+        format!(
+            "User {:?} created {} API token {}",
+            user.data.username, created.scope, created.token_id
+        )
+    );
+    let mut json = json!(created);
+    json["token"] = secret.into();
+    Ok(json)
+}
+
+// ranked after `DELETE /<table_name>/items`, which matches the same paths
+#[delete("/tokens/<token_id>", rank = 2)]
+fn revoke_token(
+    conn: OracleConnection,
+    token_id: String,
+    user: User,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    let token = get_data(
+        &*conn,
+        &ApiToken {
+            token_id,
+            username: String::new(),
+            token_hash: String::new(),
+            name: String::new(),
+            scope: String::new(),
+            created_at: Utc::now(),
+            expires_at: None,
+        },
+    )?
+    // other users' tokens look the same as missing ones
+    .filter(|token| token.username == user.data.username)
+    .ok_or_else(|| Error::KeyDoesntExistError {
+        table_name: "api_tokens".to_owned(),
+    })?;
+    info!(
+        "{}",
+        format!(
+            "User {:?} revoked API token {}",
+            user.data.username, token.token_id
+        )
+    );
+    delete_data(&*conn, Some(&user.data.username), &token)?;
+    Ok(json!(token))
+}
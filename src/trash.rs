@@ -0,0 +1,95 @@
+//! Undoing deletes and updates of table rows, for admins.
+//!
+//! Rows deleted from the table pages are kept in the `trash` table and can be restored as
+//! long as the rows they reference still exist. The last update of a row can be reverted
+//! to the version before it, taken from the audit log.
+
+use crate::auth::Admin;
+use crate::csrf::Csrf;
+use crate::database::OracleConnection;
+use crate::database_operations::{count_rows, get_data, load_data, page_records};
+use crate::error::Error;
+use crate::filter::{conditions, sort_columns, FilterQuery, SortQuery};
+use crate::read_insert_update_delete::TABLES;
+use crate::schema::TrashEntry;
+use chrono::Utc;
+use log::info;
+use rocket::Route;
+use rocket_contrib::json::JsonValue;
+
+lazy_static! {
+    pub static ref TRASH_ROUTES: Vec<Route> = routes![list_trash, restore_row, revert_row];
+}
+
+/// Deleted rows, newest first. Filters work like for table rows, e.g. `table_name=regions`.
+#[get("/admin/trash?<page_index>&<page_size>&<filters..>")]
+fn list_trash(
+    conn: OracleConnection,
+    page_index: usize,
+    page_size: usize,
+    filters: FilterQuery,
+    admin: Admin,
+) -> Result<JsonValue, Error> {
+    let (record_start, record_end) = page_records(page_index, page_size)?;
+    info!("{}", format!("Admin {:?} listed the trash", admin));
+    let conditions = conditions::<TrashEntry>(&filters)?;
+    let sort = sort_columns::<TrashEntry>(&SortQuery {
+        fields: Some("deleted_at".to_owned()),
+        orders: Some("desc".to_owned()),
+    })?;
+    let rows = count_rows::<TrashEntry>(&*conn, &conditions)?;
+    let entries = load_data::<TrashEntry>(&*conn, record_start, record_end, &conditions, &sort)?;
+    Ok(json!({ "itemsCount": rows, "data": entries }))
+}
+
+#[post("/admin/trash/<trash_id>/restore")]
+fn restore_row(
+    conn: OracleConnection,
+    trash_id: String,
+    admin: Admin,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    let entry = get_data(
+        &*conn,
+        &TrashEntry {
+            trash_id,
+            table_name: String::new(),
+            row_key: String::new(),
+            row_value: String::new(),
+            deleted_by: String::new(),
+            deleted_at: Utc::now(),
+        },
+    )?
+    .ok_or_else(|| Error::KeyDoesntExistError {
+        table_name: "trash".to_owned(),
+    })?;
+    let table = TABLES.get(&entry.table_name)?;
+    info!(
+        "{}",
+        format!(
+            "Admin {:?} restored {} row {}",
+            admin, entry.table_name, entry.row_key
+        )
+    );
+    table.restore(&*conn, &admin.data.username, &entry)
+}
+
+/// Reverts the last update of the row with the key.
+#[post("/<table_name>/items/<key>/revert")]
+fn revert_row(
+    conn: OracleConnection,
+    table_name: String,
+    key: String,
+    admin: Admin,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    let table = TABLES.get(&table_name)?;
+    info!(
+        "{}",
+        format!(
+            "Admin {:?} reverted the last update of {} row {}",
+            admin, table_name, key
+        )
+    );
+    table.revert(&*conn, &admin.data.username, &key)
+}
@@ -0,0 +1,341 @@
+//! Two-factor authentication with TOTP codes (RFC 6238) and recovery codes.
+//!
+//! A secret is enrolled unconfirmed and only used for logins once a code from the
+//! authenticator app has confirmed it. Codes are accepted one time step before or after
+//! the current one for clock drift, and never twice. Recovery codes are stored hashed and
+//! each of them works once, for when the authenticator is lost.
+//!
+//! Users with two-factor authentication (and admins without it when `require_admin_2fa`
+//! is set, who have to enroll) get a private pending login cookie instead of a session
+//! after their password, and the session starts once they enter a code.
+
+use crate::auth::User;
+use crate::csrf::Csrf;
+use crate::database::{DbConnection, OracleConnection};
+use crate::database_operations::{
+    delete_data, delete_two_factor, get_data, get_recovery_codes, insert_data, set_recovery_codes,
+    use_recovery_code, use_totp_step,
+};
+use crate::error::Error;
+use crate::lockout::LoginConfig;
+use crate::schema::{RecoveryCode, UserTotp};
+use crate::tokens::hash;
+use base32::Alphabet;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use log::info;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::Rng;
+use rocket::http::uri::Uri;
+use rocket::http::{Cookie, Cookies};
+use rocket::{Route, State};
+use rocket_contrib::json::{Json, JsonValue};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+lazy_static! {
+    pub static ref TWO_FACTOR_ROUTES: Vec<Route> =
+        routes![two_factor_status, enroll, confirm, disable];
+}
+
+pub const PENDING_LOGIN_COOKIE: &str = "pending_login";
+const PENDING_LOGIN_MINUTES: i64 = 5;
+const ISSUER: &str = "World Countries";
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// What an authenticator app needs to add the secret.
+#[derive(Serialize, Debug)]
+pub struct Enrollment {
+    pub secret: String,
+    pub uri: String,
+    pub qr_svg: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CodeInput {
+    pub code: String,
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // dynamic truncation from RFC 4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// Time step of the code if it's valid around `now`.
+fn matching_step(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let secret = base32::decode(BASE32, secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let step = now.timestamp() / STEP_SECONDS;
+    (step - 1..=step + 1).find(|s| hotp(&secret, *s as u64) == code)
+}
+
+fn find_totp(connection: &DbConnection, username: &str) -> Result<Option<UserTotp>, Error> {
+    get_data(
+        connection,
+        &UserTotp {
+            username: username.to_owned(),
+            secret: String::new(),
+            is_confirmed: String::new(),
+            last_step: None,
+            created_at: Utc::now(),
+        },
+    )
+}
+
+/// Whether the user logs in with a code after the password.
+pub fn is_enabled(connection: &DbConnection, username: &str) -> Result<bool, Error> {
+    Ok(find_totp(connection, username)?.map_or(false, |totp| totp.is_confirmed == "y"))
+}
+
+fn rejected(message: &str) -> Error {
+    Error::UserManagementError {
+        message: message.to_owned(),
+    }
+}
+
+fn enrollment(username: &str, secret: String) -> Enrollment {
+    let uri = format!(
+        "otpauth://totp/{issuer}:{user}?secret={secret}&issuer={issuer}",
+        issuer = Uri::percent_encode(ISSUER),
+        user = Uri::percent_encode(username),
+        secret = secret
+    );
+    let qr_svg = QrCode::new(uri.as_bytes())
+        .expect("otpauth uris fit in a QR code")
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Enrollment {
+        secret,
+        uri,
+        qr_svg,
+    }
+}
+
+/// Replaces an unconfirmed secret of the user with a new one.
+pub fn start_enrollment(connection: &DbConnection, username: &str) -> Result<Enrollment, Error> {
+    if let Some(old) = find_totp(connection, username)? {
+        if old.is_confirmed == "y" {
+            return Err(rejected("two-factor authentication is already enabled"));
+        }
+        delete_data(connection, None, &old)?;
+    }
+    let secret = base32::encode(BASE32, &rand::thread_rng().gen::<[u8; 20]>());
+    insert_data(
+        connection,
+        None,
+        &UserTotp {
+            username: username.to_owned(),
+            secret: secret.clone(),
+            is_confirmed: "n".to_owned(),
+            last_step: None,
+            created_at: Utc::now(),
+        },
+    )?;
+    Ok(enrollment(username, secret))
+}
+
+/// The unconfirmed secret of the user, if there is one.
+pub fn pending_enrollment(
+    connection: &DbConnection,
+    username: &str,
+) -> Result<Option<Enrollment>, Error> {
+    Ok(find_totp(connection, username)?
+        .filter(|totp| totp.is_confirmed != "y")
+        .map(|totp| enrollment(username, totp.secret)))
+}
+
+/// Checks the code against the secret, which confirms it, and remembers its time step
+/// so that the code can't be used again, also not by a request at the same time.
+fn accept_code(connection: &DbConnection, totp: &UserTotp, code: &str) -> Result<bool, Error> {
+    match matching_step(&totp.secret, code, Utc::now()) {
+        Some(step) => use_totp_step(connection, totp, step),
+        None => Ok(false),
+    }
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Replaces the recovery codes of the user with new ones, which are returned.
+fn new_recovery_codes(connection: &DbConnection, username: &str) -> Result<Vec<String>, Error> {
+    let codes = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = base32::encode(BASE32, &rand::thread_rng().gen::<[u8; 5]>()).to_lowercase();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect::<Vec<_>>();
+    let stored = codes
+        .iter()
+        .map(|code| RecoveryCode {
+            username: username.to_owned(),
+            code_hash: hash(&normalize_recovery_code(code)),
+        })
+        .collect::<Vec<_>>();
+    set_recovery_codes(connection, username, &stored)?;
+    Ok(codes)
+}
+
+/// Confirms the enrolled secret with a code from the authenticator,
+/// and returns the new recovery codes if the code is right.
+pub fn confirm_enrollment(
+    connection: &DbConnection,
+    username: &str,
+    code: &str,
+) -> Result<Option<Vec<String>>, Error> {
+    let totp = find_totp(connection, username)?
+        .filter(|totp| totp.is_confirmed != "y")
+        .ok_or_else(|| rejected("there is no enrollment to confirm"))?;
+    if accept_code(connection, &totp, code)? {
+        Ok(Some(new_recovery_codes(connection, username)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Checks a code from the authenticator or a recovery code, which is used up.
+pub fn verify(connection: &DbConnection, username: &str, code: &str) -> Result<bool, Error> {
+    let totp = match find_totp(connection, username)? {
+        Some(totp) if totp.is_confirmed == "y" => totp,
+        _ => return Ok(false),
+    };
+    if accept_code(connection, &totp, code)? {
+        return Ok(true);
+    }
+    let recovery_code = RecoveryCode {
+        username: username.to_owned(),
+        code_hash: hash(&normalize_recovery_code(code)),
+    };
+    let used = use_recovery_code(connection, &recovery_code)?;
+    if used {
+        info!("{}", format!("User {:?} used a recovery code", username));
+    }
+    Ok(used)
+}
+
+/// Remembers that the user gave the right password, for the second login step.
+pub fn start_pending_login(cookies: &mut Cookies, username: &str) {
+    cookies.add_private(Cookie::new(
+        PENDING_LOGIN_COOKIE,
+        format!("{}\n{}", Utc::now().to_rfc3339(), username),
+    ));
+}
+
+/// The user who gave the right password in the last few minutes, if any.
+pub fn pending_login(cookies: &mut Cookies) -> Option<String> {
+    let cookie = cookies.get_private(PENDING_LOGIN_COOKIE)?;
+    let mut parts = cookie.value().splitn(2, '\n');
+    let started = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+    let username = parts.next()?;
+    if started.with_timezone(&Utc) + Duration::minutes(PENDING_LOGIN_MINUTES) < Utc::now() {
+        return None;
+    }
+    Some(username.to_owned())
+}
+
+pub fn end_pending_login(cookies: &mut Cookies) {
+    cookies.remove_private(Cookie::named(PENDING_LOGIN_COOKIE));
+}
+
+/// Two-factor authentication is managed from a login session,
+/// so that a leaked API token can't turn it off.
+fn check_session(user: &User) -> Result<(), Error> {
+    match user.token {
+        Some(_) => Err(rejected(
+            "two-factor authentication can't be managed with an API token",
+        )),
+        None => Ok(()),
+    }
+}
+
+#[get("/two_factor")]
+fn two_factor_status(
+    conn: OracleConnection,
+    user: User,
+    login_config: State<LoginConfig>,
+) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    Ok(json!({
+        "enabled": is_enabled(&*conn, &user.data.username)?,
+        "required": user.is_admin && login_config.require_admin_2fa,
+        "recovery_codes_left": get_recovery_codes(&*conn, &user.data.username)?.len(),
+    }))
+}
+
+#[post("/two_factor/enrollment")]
+fn enroll(conn: OracleConnection, user: User, _csrf: Csrf) -> Result<Json<Enrollment>, Error> {
+    check_session(&user)?;
+    Ok(Json(start_enrollment(&*conn, &user.data.username)?))
+}
+
+#[post("/two_factor/confirmation", format = "json", data = "<input>")]
+fn confirm(
+    conn: OracleConnection,
+    input: Json<CodeInput>,
+    user: User,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    match confirm_enrollment(&*conn, &user.data.username, &input.code)? {
+        Some(recovery_codes) => {
+            info!(
+                "{}",
+                format!(
+                    "User {:?} enabled two-factor authentication",
+                    user.data.username
+                )
+            );
+            Ok(json!({ "recovery_codes": recovery_codes }))
+        }
+        None => Err(rejected("the code is wrong")),
+    }
+}
+
+/// Turns two-factor authentication off, which takes a current code.
+#[delete("/two_factor", format = "json", data = "<input>")]
+fn disable(
+    conn: OracleConnection,
+    input: Json<CodeInput>,
+    user: User,
+    login_config: State<LoginConfig>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    check_session(&user)?;
+    if user.is_admin && login_config.require_admin_2fa {
+        return Err(rejected("admins have to use two-factor authentication"));
+    }
+    if !verify(&*conn, &user.data.username, &input.code)? {
+        return Err(rejected("the code is wrong"));
+    }
+    info!(
+        "{}",
+        format!(
+            "User {:?} disabled two-factor authentication",
+            user.data.username
+        )
+    );
+    delete_two_factor(&*conn, &user.data.username)?;
+    Ok(json!({}))
+}
@@ -0,0 +1,329 @@
+use crate::auth::{Admin, User};
+use crate::csrf::Csrf;
+use crate::database::{DbConnection, OracleConnection};
+use crate::database_operations::{
+    count_rows, delete_two_factor, delete_user, delete_user_sessions, get_data, get_user,
+    get_user_roles, insert_data, load_data, page_records, set_user_roles, transaction, update_data,
+    ADMIN_ROLE,
+};
+use crate::error::Error;
+use crate::lockout::{self, LoginConfig};
+use crate::password::{self, PasswordPolicy};
+use crate::schema::{Role, UserInfo};
+use crate::sessions::ClientInfo;
+use crate::two_factor;
+use log::{info, warn};
+use rocket::{Route, State};
+use rocket_contrib::json::{Json, JsonValue};
+use serde::Deserialize;
+
+lazy_static! {
+    pub static ref USER_ROUTES: Vec<Route> = routes![
+        list_users,
+        create_user,
+        change_user,
+        remove_user,
+        change_own_password
+    ];
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    /// Adds the admin role to `roles`.
+    #[serde(default)]
+    pub is_admin: bool,
+    #[serde(default = "default_roles")]
+    pub roles: Vec<String>,
+}
+
+/// New users can read and add rows, like before roles were introduced.
+fn default_roles() -> Vec<String> {
+    vec!["editor".to_owned()]
+}
+
+/// Changes to a user, missing fields are left as they are.
+#[derive(Deserialize, Debug)]
+pub struct UserChange {
+    pub password: Option<String>,
+    /// Adds or removes the admin role.
+    pub is_admin: Option<bool>,
+    pub is_disabled: Option<bool>,
+    /// `false` unlocks a login locked by failed attempts.
+    pub is_locked: Option<bool>,
+    /// `false` turns off two-factor authentication, for users who lost their authenticator.
+    pub two_factor: Option<bool>,
+    /// Replaces all roles of the user.
+    pub roles: Option<Vec<String>>,
+}
+
+/// A password change by the user, who has to know the current password.
+#[derive(Deserialize, Debug)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+fn flag(value: bool) -> String {
+    if value { "y" } else { "n" }.to_owned()
+}
+
+fn has_admin_role(roles: &[String]) -> bool {
+    roles.iter().any(|role| role == ADMIN_ROLE)
+}
+
+/// `roles` with the admin role added for `Some(true)` or removed for `Some(false)`.
+fn with_admin_role(mut roles: Vec<String>, is_admin: Option<bool>) -> Vec<String> {
+    match is_admin {
+        Some(true) if !has_admin_role(&roles) => roles.push(ADMIN_ROLE.to_owned()),
+        Some(false) => roles.retain(|role| role != ADMIN_ROLE),
+        _ => {}
+    }
+    roles
+}
+
+fn user_json(connection: &DbConnection, user: &UserInfo) -> Result<JsonValue, Error> {
+    let locked_until = lockout::locked_until(connection, &user.username)?;
+    let roles = get_user_roles(connection, &user.username)?;
+    Ok(json!({
+        "username": user.username,
+        "is_admin": has_admin_role(&roles),
+        "is_disabled": user.is_disabled == "y",
+        "is_locked": locked_until.is_some(),
+        "two_factor": two_factor::is_enabled(connection, &user.username)?,
+        "locked_until": locked_until,
+        "last_appearance": user.last_appearance,
+        "roles": roles,
+    }))
+}
+
+fn find_user(connection: &DbConnection, username: &str) -> Result<UserInfo, Error> {
+    get_user(
+        connection,
+        &UserInfo {
+            username: username.to_owned(),
+            ..UserInfo::default()
+        },
+    )?
+    .ok_or_else(|| Error::KeyDoesntExistError {
+        table_name: "users_info".to_owned(),
+    })
+}
+
+fn rejected(message: &str) -> Error {
+    Error::UserManagementError {
+        message: message.to_owned(),
+    }
+}
+
+/// Roles can only be assigned if they exist, so that users don't end up without them.
+fn check_roles(connection: &DbConnection, roles: &[String]) -> Result<(), Error> {
+    for name in roles {
+        let role = Role { name: name.clone() };
+        if get_data(connection, &role)?.is_none() {
+            return Err(rejected(&format!("unknown role {:?}", name)));
+        }
+    }
+    Ok(())
+}
+
+#[get("/admin/users?<page_index>&<page_size>")]
+fn list_users(
+    conn: OracleConnection,
+    page_index: usize,
+    page_size: usize,
+    admin: Admin,
+) -> Result<JsonValue, Error> {
+    let (record_start, record_end) = page_records(page_index, page_size)?;
+    info!("{}", format!("Admin {:?} listed users", admin));
+    let rows = count_rows::<UserInfo>(&*conn, &[])?;
+    let users = load_data::<UserInfo>(&*conn, record_start, record_end, &[], &[])?;
+    Ok(json!({
+        "itemsCount": rows,
+        "data": users
+            .iter()
+            .map(|u| user_json(&*conn, u).map(|json| json.0))
+            .collect::<Result<Vec<_>, _>>()?,
+    }))
+}
+
+#[post("/admin/users", format = "json", data = "<user>")]
+fn create_user(
+    conn: OracleConnection,
+    user: Json<NewUser>,
+    admin: Admin,
+    policy: State<PasswordPolicy>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if user.username.trim().is_empty() || user.password.is_empty() {
+        return Err(rejected("username and password can't be empty"));
+    }
+    let roles = if user.is_admin {
+        with_admin_role(user.roles.clone(), Some(true))
+    } else {
+        user.roles.clone()
+    };
+    let new = UserInfo {
+        username: user.username.trim().to_owned(),
+        is_admin: flag(has_admin_role(&roles)),
+        ..UserInfo::default()
+    };
+    password::check_policy(&*conn, &policy, &new, &user.password)?;
+    check_roles(&*conn, &roles)?;
+    let new = UserInfo {
+        password: password::hash(&user.password)?,
+        ..new
+    };
+    info!(
+        "{}",
+        format!("Admin {:?} created user {}", admin, user.username)
+    );
+    let created = transaction(&*conn, || {
+        let created = insert_data(&*conn, Some(&admin.data.username), &new)?;
+        set_user_roles(&*conn, &created.username, &roles)?;
+        Ok(created)
+    })?;
+    user_json(&*conn, &created)
+}
+
+#[put("/admin/users/<username>", format = "json", data = "<change>")]
+fn change_user(
+    conn: OracleConnection,
+    username: String,
+    change: Json<UserChange>,
+    admin: Admin,
+    policy: State<PasswordPolicy>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    // the admin flag is the admin role
+    let roles = match (&change.roles, change.is_admin) {
+        (None, None) => None,
+        (roles, is_admin) => {
+            let roles = match roles {
+                Some(roles) => roles.clone(),
+                None => get_user_roles(&*conn, &username)?,
+            };
+            Some(with_admin_role(roles, is_admin))
+        }
+    };
+    // admins can't lock themselves out
+    if username == admin.data.username
+        && (roles.iter().any(|roles| !has_admin_role(roles)) || change.is_disabled == Some(true))
+    {
+        return Err(rejected(
+            "you can't revoke your own admin rights or disable yourself",
+        ));
+    }
+    if change.is_locked == Some(true) {
+        return Err(rejected("logins are only locked by failed attempts"));
+    }
+    if change.two_factor == Some(true) {
+        return Err(rejected(
+            "users enable two-factor authentication themselves",
+        ));
+    }
+    if change.password.as_ref().map_or(false, String::is_empty) {
+        return Err(rejected("password can't be empty"));
+    }
+    if let Some(roles) = &roles {
+        check_roles(&*conn, roles)?;
+    }
+    info!(
+        "{}",
+        format!(
+            "Admin {:?} changed user {}: password reset {}, admin {:?}, disabled {:?}, \
+             locked {:?}, two-factor {:?}, roles {:?}",
+            admin,
+            username,
+            change.password.is_some(),
+            change.is_admin,
+            change.is_disabled,
+            change.is_locked,
+            change.two_factor,
+            change.roles
+        )
+    );
+    // all of the changes are made, or none of them
+    let updated = transaction(&*conn, || {
+        let old = find_user(&*conn, &username)?;
+        // the password goes through the policy and into the history first
+        let old = match &change.password {
+            Some(new_password) => password::change(&*conn, &policy, &old, new_password)?,
+            None => old,
+        };
+        let new = UserInfo {
+            is_disabled: change
+                .is_disabled
+                .map_or_else(|| old.is_disabled.clone(), flag),
+            ..old.clone()
+        };
+        let updated = update_data(&*conn, Some(&admin.data.username), &old, &new)?;
+        if change.is_disabled == Some(true) {
+            delete_user_sessions(&*conn, &username)?;
+        }
+        if change.is_locked == Some(false) {
+            lockout::reset(&*conn, &username)?;
+        }
+        if change.two_factor == Some(false) {
+            delete_two_factor(&*conn, &username)?;
+        }
+        if let Some(roles) = &roles {
+            set_user_roles(&*conn, &username, roles)?;
+        }
+        Ok(updated)
+    })?;
+    user_json(&*conn, &updated)
+}
+
+#[delete("/admin/users/<username>")]
+fn remove_user(
+    conn: OracleConnection,
+    username: String,
+    admin: Admin,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if username == admin.data.username {
+        return Err(rejected("you can't delete yourself"));
+    }
+    let user = find_user(&*conn, &username)?;
+    info!("{}", format!("Admin {:?} deleted user {}", admin, username));
+    let deleted = user_json(&*conn, &user)?;
+    delete_user(&*conn, &admin.data.username, &user)?;
+    // a new user with the same name starts without failures
+    lockout::reset(&*conn, &username)?;
+    Ok(deleted)
+}
+
+/// Changes the password of the signed in user, also when it has expired.
+#[put("/password", format = "json", data = "<change>")]
+fn change_own_password(
+    conn: OracleConnection,
+    change: Json<PasswordChange>,
+    user: User,
+    client: ClientInfo,
+    policy: State<PasswordPolicy>,
+    login_config: State<LoginConfig>,
+    _csrf: Csrf,
+) -> Result<JsonValue, Error> {
+    if user.token.is_some() {
+        return Err(rejected("passwords can't be changed with an API token"));
+    }
+    let old = find_user(&*conn, &user.data.username)?;
+    if !password::verify(&change.current_password, &old.password)? {
+        // guessing the current password counts like failed logins
+        let address = client.ip_address.as_deref();
+        lockout::record_failure(&*conn, &login_config, &old.username, address)?;
+        warn!(
+            "{}",
+            format!("Wrong current password for user {:?}", old.username)
+        );
+        return Err(rejected("the current password is wrong"));
+    }
+    let updated = password::change(&*conn, &policy, &old, &change.new_password)?;
+    info!(
+        "{}",
+        format!("User {:?} changed their password", updated.username)
+    );
+    user_json(&*conn, &updated)
+}